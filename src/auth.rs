@@ -0,0 +1,181 @@
+/// Registry authentication from Docker config JSON pull secrets
+use log::debug;
+use serde_json::{Map, Value};
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::secrets;
+
+/// Authfile passed to podman, removed on drop when it was created by us
+pub struct Authfile {
+    pub path: PathBuf,
+    temporary: bool,
+}
+
+impl Drop for Authfile {
+    fn drop(&mut self) {
+        if self.temporary {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Read pull secret or authfile as JSON object
+fn read_secret(path: &Path) -> Result<Map<String, Value>, String> {
+    let data = match fs::read_to_string(path) {
+        Ok(d) => d,
+        Err(e) => {
+            return Err(format!(
+                "Unable to read pull secret {}: {e}",
+                path.display()
+            ))
+        }
+    };
+    match serde_json::from_str(&data) {
+        Ok(Value::Object(o)) => Ok(o),
+        Ok(_) => Err(format!(
+            "Pull secret {} must be a JSON object",
+            path.display()
+        )),
+        Err(e) => Err(format!(
+            "Unable to parse pull secret {}: {e}",
+            path.display()
+        )),
+    }
+}
+
+/// Registry credentials of pull secret, both with and without top level `auths` object
+fn secret_auths(mut secret: Map<String, Value>) -> Map<String, Value> {
+    match secret.remove("auths") {
+        Some(Value::Object(auths)) => auths,
+        _ => secret,
+    }
+}
+
+/// Write credentials into temporary authfile removed on drop
+fn write_authfile(path: PathBuf, auths: Map<String, Value>) -> Result<Authfile, String> {
+    let authfile = Authfile {
+        path,
+        temporary: true,
+    };
+    let mut content = Map::new();
+    content.insert("auths".to_owned(), Value::Object(auths));
+    // Credentials must not be readable by other users
+    let written = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&authfile.path)
+        .and_then(|mut f| f.write_all(Value::Object(content).to_string().as_bytes()));
+    if let Err(e) = written {
+        return Err(format!(
+            "Unable to write authfile {}: {e}",
+            authfile.path.display()
+        ));
+    }
+    Ok(authfile)
+}
+
+/// Prepare podman authfile from pull secret
+///
+/// Secrets in `.dockerconfigjson` format, with top level `auths` object, are used
+/// directly. Legacy `.dockercfg` secrets, mapping registries directly to credentials,
+/// are wrapped into `auths` object and written to temporary authfile.
+pub fn prepare_authfile(path: &str) -> Result<Authfile, String> {
+    let secret = read_secret(Path::new(path))?;
+    if secret.contains_key("auths") {
+        return Ok(Authfile {
+            path: PathBuf::from(path),
+            temporary: false,
+        });
+    }
+
+    let authfile = env::temp_dir().join(format!("iguana-authfile-{}.json", process::id()));
+    debug!(
+        "Converting pull secret {path} to authfile {}",
+        authfile.display()
+    );
+    write_authfile(authfile, secret_auths(secret))
+}
+
+/// Standard base64 encoding with padding, as used by `auth` field of authfiles
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Credentials of a container registry
+pub enum Credentials<'a> {
+    Login {
+        username: &'a str,
+        password: &'a str,
+    },
+    /// Pull secret or authfile containing credentials of the registry
+    Authfile(&'a Path),
+}
+
+/// Prepare temporary authfile with credentials of workflow registries
+///
+/// Credentials of base authfile, e.g. from `--image-pull-secret`, are kept unless
+/// the registry is listed in the workflow as well.
+pub fn registries_authfile<'a>(
+    base: Option<&Path>,
+    registries: impl IntoIterator<Item = (&'a str, Credentials<'a>)>,
+) -> Result<Authfile, String> {
+    let mut auths = match base {
+        Some(path) => secret_auths(read_secret(path)?),
+        None => Map::new(),
+    };
+    for (registry, credentials) in registries {
+        let auth = match credentials {
+            Credentials::Login { username, password } => {
+                let auth = base64(format!("{username}:{password}").as_bytes());
+                secrets::register([&auth]);
+                let mut entry = Map::new();
+                entry.insert("auth".to_owned(), Value::String(auth));
+                Value::Object(entry)
+            }
+            Credentials::Authfile(path) => {
+                match secret_auths(read_secret(path)?).remove(registry) {
+                    Some(auth) => auth,
+                    None => {
+                        return Err(format!(
+                            "Authfile {} has no credentials for registry {registry}",
+                            path.display()
+                        ))
+                    }
+                }
+            }
+        };
+        auths.insert(registry.to_owned(), auth);
+    }
+
+    let authfile = env::temp_dir().join(format!("iguana-registries-{}.json", process::id()));
+    debug!(
+        "Writing registry credentials to authfile {}",
+        authfile.display()
+    );
+    write_authfile(authfile, auths)
+}
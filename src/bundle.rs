@@ -0,0 +1,54 @@
+/// Workflow bundles, tar archives with control file and the files it references
+use log::debug;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{self, Command};
+
+/// Control file expected in the top directory of the bundle
+pub const BUNDLE_CONTROL_FILE: &str = "control.yaml";
+
+/// Check whether workflow path points to a bundle
+pub fn is_bundle(path: &str) -> bool {
+    path.ends_with(".tar") || is_compressed(path)
+}
+
+fn is_compressed(path: &str) -> bool {
+    path.ends_with(".tar.gz") || path.ends_with(".tgz")
+}
+
+/// Extract bundle into new temporary directory and return path to it
+pub fn extract_bundle(path: &str) -> Result<PathBuf, String> {
+    let dir = env::temp_dir().join(format!("iguana-bundle-{}", process::id()));
+    if let Err(e) = fs::create_dir_all(&dir) {
+        return Err(format!(
+            "Unable to create bundle directory {}: {e}",
+            dir.display()
+        ));
+    }
+
+    let mut tar = Command::new("tar");
+    let mut cmd = tar.arg("-x");
+    if is_compressed(path) {
+        cmd = cmd.arg("-z");
+    }
+    cmd = cmd.arg("-f").arg(path).arg("-C").arg(&dir);
+    debug!("{cmd:?}");
+    match cmd.status() {
+        Ok(status) if !status.success() => {
+            return Err(format!(
+                "Unable to extract bundle {path}: tar exited with {status}"
+            ));
+        }
+        Ok(_) => {}
+        Err(e) => return Err(format!("Unable to extract bundle {path}: {e}")),
+    }
+
+    if !dir.join(BUNDLE_CONTROL_FILE).is_file() {
+        return Err(format!(
+            "Bundle {path} does not contain {BUNDLE_CONTROL_FILE}"
+        ));
+    }
+    Ok(dir)
+}
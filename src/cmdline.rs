@@ -0,0 +1,101 @@
+/// Environment passed on kernel command line, e.g. `iguana.tag=foo` becomes `TAG=foo`
+use linked_hash_map::LinkedHashMap;
+use log::warn;
+
+use std::fs;
+
+use crate::workflow::{env_key_problem, env_name};
+
+/// Kernel command line of the running system
+const CMDLINE: &str = "/proc/cmdline";
+
+/// Split command line into parameters, double quotes group spaces and are removed
+fn split_params(cmdline: &str) -> Vec<String> {
+    let mut params = Vec::new();
+    let mut param = String::new();
+    let mut quoted = false;
+    for c in cmdline.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !param.is_empty() {
+                    params.push(std::mem::take(&mut param));
+                }
+            }
+            c => param.push(c),
+        }
+    }
+    if !param.is_empty() {
+        params.push(param);
+    }
+    params
+}
+
+/// Variables of parameters named `<prefix>.<name>`, named by uppercased rest of the key
+///
+/// Prefix may be given with the trailing dot as well. Parameters without value are set
+/// to `1`, later parameters override earlier ones.
+pub fn parse_cmdline(cmdline: &str, prefix: &str) -> LinkedHashMap<String, String> {
+    let prefix = format!("{}.", prefix.strip_suffix('.').unwrap_or(prefix));
+    let mut env = LinkedHashMap::new();
+    for param in split_params(cmdline) {
+        let (key, value) = param.split_once('=').unwrap_or((&param, "1"));
+        match key.strip_prefix(&prefix) {
+            Some(name) if !name.is_empty() => {
+                let name = env_name(name);
+                // Boot must not fail because of unrelated parameter
+                match env_key_problem(&name) {
+                    Some(problem) => warn!("Ignoring kernel parameter {key} with {problem}"),
+                    None => {
+                        env.insert(name, value.to_owned());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    env
+}
+
+/// Variables of kernel command line parameters starting with prefix, none when the
+/// command line can not be read
+pub fn cmdline_env(prefix: &str) -> LinkedHashMap<String, String> {
+    match fs::read_to_string(CMDLINE) {
+        Ok(cmdline) => parse_cmdline(&cmdline, prefix),
+        Err(e) => {
+            warn!("Unable to read kernel command line {CMDLINE}: {e}");
+            LinkedHashMap::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(cmdline: &str, prefix: &str) -> Vec<String> {
+        parse_cmdline(cmdline, prefix)
+            .iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect()
+    }
+
+    #[test]
+    fn prefix_is_stripped_with_separator() {
+        let cmdline = "root=/dev/sda1 iguana.tag=foo iguanafoo=1 iguana.disk-layout=lvm";
+        assert_eq!(vars(cmdline, "iguana"), ["TAG=foo", "DISK_LAYOUT=lvm"]);
+        assert_eq!(vars(cmdline, "iguana."), ["TAG=foo", "DISK_LAYOUT=lvm"]);
+    }
+
+    #[test]
+    fn quotes_group_spaces_of_values() {
+        let cmdline = "quiet iguana.label=\"install disk\" iguana.tag=\"\"";
+        assert_eq!(vars(cmdline, "iguana"), ["LABEL=install disk", "TAG="]);
+    }
+
+    #[test]
+    fn bare_flags_are_set_to_one() {
+        let cmdline = "iguana.debug iguana. iguana.tag=a iguana.tag=b";
+        assert_eq!(vars(cmdline, "iguana"), ["DEBUG=1", "TAG=b"]);
+    }
+}
@@ -1,12 +1,24 @@
 /// Container engines traits
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
 
-use crate::workflow::{Container, WorkflowOptions};
+use linked_hash_map::LinkedHashMap;
+
+use crate::workflow::{Container, PullPolicy, WorkflowError, WorkflowOptions};
 
 pub(crate) mod podman;
 
 pub trait ImageOps {
-    fn prepare_image(&self, image: &str, dry_run: bool) -> Result<(), String>;
+    fn prepare_image(
+        &self,
+        image: &str,
+        policy: PullPolicy,
+        opts: &WorkflowOptions,
+    ) -> Result<(), WorkflowError>;
+    fn image_exists(&self, image: &str, opts: &WorkflowOptions) -> Result<bool, String>;
+    /// Resolve image to digest without pulling it
+    fn image_digest(&self, image: &str, opts: &WorkflowOptions) -> Result<String, String>;
     fn clean_image(&self, image: &str, opts: &WorkflowOptions) -> Result<(), String>;
 }
 
@@ -14,13 +26,95 @@ pub trait VolumeOps {
     fn prepare_volume(&self, volume_src: &str, opts: &WorkflowOptions) -> Result<(), String>;
     fn clean_volumes(&self, volumes: &HashSet<&str>, opts: &WorkflowOptions) -> Result<(), String>;
 }
+/// Container engine running jobs, any engine implementing all operations
+pub trait Engine: ImageOps + ContainerOps + VolumeOps + Sync {}
+
+impl<T: ImageOps + ContainerOps + VolumeOps + Sync> Engine for T {}
+
+/// Parameters of a single container run
+pub struct ContainerRun<'a> {
+    /// Container name
+    pub name: &'a str,
+    /// Image to run, either container image or image committed from another job
+    pub image: &'a str,
+    pub container: &'a Container,
+    /// Run in background, services always are, main container when requested
+    pub detach: bool,
+    pub env: LinkedHashMap<String, String>,
+    /// Terminate foreground container after timeout
+    pub timeout: Option<Duration>,
+    /// Do not remove container after it exits
+    pub keep: bool,
+    /// Run given shell attached to terminal instead of the image entrypoint
+    pub shell: Option<&'a str>,
+    /// Keep container idle in background instead of running the image entrypoint,
+    /// so commands can be executed in it
+    pub idle: bool,
+    /// Host file mounted into container for job outputs
+    pub output: Option<&'a Path>,
+    /// Host directory mounted into container for outputs of job steps
+    pub step_outputs: Option<&'a Path>,
+    /// File to which output of foreground container is appended instead of the terminal
+    pub log: Option<&'a Path>,
+    /// Prefix of output lines forwarded to the log, `job/container`
+    pub prefix: &'a str,
+}
+
+/// Parameters of a single command executed in running container
+pub struct ContainerExec<'a> {
+    /// Shell command
+    pub cmd: &'a str,
+    /// Shell running the command with `-c`
+    pub shell: &'a str,
+    /// Standard input of the command, none when not set
+    pub input: Option<&'a str>,
+    pub env: &'a LinkedHashMap<String, String>,
+    /// Working directory of the command, container one when not set
+    pub workdir: Option<&'a str>,
+    /// File to which output of the command is appended instead of the terminal
+    pub log: Option<&'a Path>,
+    /// Prefix of output lines forwarded to the log, `job/container`
+    pub prefix: &'a str,
+    /// Terminate container after timeout
+    pub timeout: Option<Duration>,
+}
+
 pub trait ContainerOps {
     fn run_container(
         &self,
-        container: &Container,
-        is_service: bool,
-        env: HashMap<String, String>,
+        run: ContainerRun,
         opts: &WorkflowOptions,
-    ) -> Result<(), String>;
+    ) -> Result<Option<i32>, WorkflowError>;
     fn stop_container(&self, name: &str, opts: &WorkflowOptions) -> Result<(), String>;
+    fn kill_container(&self, name: &str, opts: &WorkflowOptions) -> Result<(), String>;
+    fn remove_container(&self, name: &str, opts: &WorkflowOptions) -> Result<(), String>;
+    fn commit_container(
+        &self,
+        name: &str,
+        image: &str,
+        opts: &WorkflowOptions,
+    ) -> Result<(), String>;
+    fn check_health(&self, name: &str, cmd: &str, opts: &WorkflowOptions) -> Result<bool, String>;
+    /// Whether directory exists in running container
+    fn dir_exists(&self, name: &str, path: &str, opts: &WorkflowOptions) -> Result<bool, String>;
+    /// Whether shell can be run in running container
+    fn shell_exists(&self, name: &str, shell: &str, opts: &WorkflowOptions)
+        -> Result<bool, String>;
+    /// Execute shell command in running container and return its exit code
+    fn exec_in_container(
+        &self,
+        name: &str,
+        exec: ContainerExec,
+        opts: &WorkflowOptions,
+    ) -> Result<Option<i32>, WorkflowError>;
+    /// Copy file or directory from container into host directory
+    fn copy_from_container(
+        &self,
+        name: &str,
+        src: &str,
+        dest: &Path,
+        opts: &WorkflowOptions,
+    ) -> Result<(), String>;
+    /// Stream logs of running container into file in background until the container stops
+    fn follow_logs(&self, name: &str, path: &Path, opts: &WorkflowOptions) -> Result<(), String>;
 }
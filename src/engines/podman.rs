@@ -1,37 +1,722 @@
-use log::debug;
+use linked_hash_map::LinkedHashMap;
+use log::{debug, error, info, warn};
+use serde_json::Value;
 /// Podman container engine
-use std::collections::{HashMap, HashSet};
-use std::process::Command;
+use std::collections::hash_map::RandomState;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::Mutex;
+use std::thread::{self, sleep, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::engines::{ContainerOps, ImageOps, VolumeOps};
-use crate::workflow::{Container, WorkflowOptions};
+use crate::engines::{ContainerExec, ContainerOps, ContainerRun, ImageOps, VolumeOps};
+use crate::interrupt;
+use crate::workflow::{
+    EnvOrder, PullPolicy, Runtime, TimeoutAction, WorkflowError, WorkflowOptions, HOST_NETWORK,
+};
 
 pub struct Podman;
 
+/// Mount point of the iguana state directory inside containers
+const IGUANA_TARGET: &str = "/iguana";
+/// Mount point of the new root inside containers
+const NEWROOT_TARGET: &str = "/sysroot";
+/// Path of job outputs file inside containers
+const OUTPUT_TARGET: &str = "/run/iguana/output";
+/// Mount point of the workflow file directory inside containers
+const WORKFLOW_DIR_TARGET: &str = "/run/iguana/workflow";
+
+/// Path of step outputs directory inside containers, every step with id writes its own file
+pub const STEP_OUTPUTS_TARGET: &str = "/run/iguana/steps";
+/// Mount point of local action directory inside containers
+pub const ACTION_TARGET: &str = "/run/iguana/action";
+
+/// Shell killing processes of timed out exec, present wherever default step shell is
+const KILL_SHELL: &str = "/bin/sh";
+
+/// Images pulled or found present during the workflow run, not checked again
+/// unless pull policy is always
+static PULLED: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+fn is_pulled(image: &str) -> bool {
+    PULLED
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|p| p.contains(image))
+}
+
+fn set_pulled(image: &str, pulled: bool) {
+    let mut guard = PULLED.lock().unwrap();
+    let images = guard.get_or_insert_with(HashSet::new);
+    if pulled {
+        images.insert(image.to_owned());
+    } else {
+        images.remove(image);
+    }
+}
+
+/// Forget images pulled by previous workflow run
+pub fn reset_pulled() {
+    *PULLED.lock().unwrap() = None;
+}
+
+/// Differences of docker command line from podman one
+impl Runtime {
+    fn binary(self) -> &'static str {
+        match self {
+            Runtime::Podman => "podman",
+            Runtime::Docker => "docker",
+        }
+    }
+
+    /// Options of registry accessing subcommand allowing insecure registries,
+    /// docker configures them in its daemon for pulls
+    fn tls_args(self, subcommand: &str) -> &'static [&'static str] {
+        match (self, subcommand) {
+            (Runtime::Podman, _) => &["--tls-verify=false"],
+            (Runtime::Docker, "manifest") => &["--insecure"],
+            (Runtime::Docker, _) => &[],
+        }
+    }
+
+    /// Subcommand succeeding when given object, e.g. image or volume, exists
+    fn exists_args(self, object: &'static str) -> [&'static str; 2] {
+        match self {
+            Runtime::Podman => [object, "exists"],
+            Runtime::Docker => [object, "inspect"],
+        }
+    }
+
+    /// Container options marking containers run by iguana
+    fn marker_args(self) -> &'static [&'static str] {
+        match self {
+            Runtime::Podman => &["--replace", "--annotation=iguana=true"],
+            // Docker can not replace existing container, it is removed before run
+            Runtime::Docker => &["--label=iguana=true"],
+        }
+    }
+
+    /// Options of container stop and removal ignoring missing containers,
+    /// docker reports them as an error checked by `missing_container`
+    fn ignore_args(self) -> &'static [&'static str] {
+        match self {
+            Runtime::Podman => &["--ignore"],
+            Runtime::Docker => &[],
+        }
+    }
+
+    /// Image inspect format of image digest, docker has only digests of repositories
+    fn digest_format(self) -> &'static str {
+        match self {
+            Runtime::Podman => "--format={{.Digest}}",
+            Runtime::Docker => "--format={{index .RepoDigests 0}}",
+        }
+    }
+
+    /// Image inspect format of image creation time
+    fn created_format(self) -> &'static str {
+        match self {
+            Runtime::Podman => "--format={{.Created.Unix}}",
+            Runtime::Docker => "--format={{.Created}}",
+        }
+    }
+
+    /// Parse image creation time printed using `created_format`
+    fn parse_created(self, created: &str) -> Result<SystemTime, String> {
+        match self {
+            Runtime::Podman => match created.parse() {
+                Ok(c) => Ok(UNIX_EPOCH + Duration::from_secs(c)),
+                Err(e) => Err(e.to_string()),
+            },
+            Runtime::Docker => humantime::parse_rfc3339_weak(created).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// Whether container exists and is running, missing container is not running
+fn container_running(name: &str, opts: &WorkflowOptions) -> bool {
+    let mut podman = runtime_command(opts);
+    let cmd = podman.args([
+        "container",
+        "inspect",
+        "--format={{.State.Running}}",
+        "--",
+        name,
+    ]);
+    log_command(cmd, opts);
+    match cmd.stderr(Stdio::null()).output() {
+        Ok(o) => o.status.success() && String::from_utf8_lossy(&o.stdout).trim() == "true",
+        Err(_) => false,
+    }
+}
+
+/// Runtime command with global options placed before any subcommand
+fn runtime_command(opts: &WorkflowOptions) -> Command {
+    let mut podman = match &opts.runtime_path {
+        Some(path) => Command::new(path),
+        None => Command::new(opts.runtime.binary()),
+    };
+    podman.args(&opts.podman_global_args);
+    podman
+}
+
+/// Log runtime command, in dry run every command is shown with default log level
+fn log_command(cmd: &Command, opts: &WorkflowOptions) {
+    match opts.dry_run {
+        true => info!("{cmd:?}"),
+        false => debug!("{cmd:?}"),
+    }
+}
+
+/// Stdout and stderr appending to log file
+fn log_stdio(path: &Path) -> Result<(File, File), String> {
+    let file = match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(f) => f,
+        Err(e) => return Err(format!("Unable to open log file {}: {e}", path.display())),
+    };
+    match file.try_clone() {
+        Ok(stderr) => Ok((file, stderr)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Whether failed command failed only because container does not exist
+fn missing_container(stderr: &[u8]) -> bool {
+    String::from_utf8_lossy(stderr).contains("No such container")
+}
+
+/// Architecture of the host in the naming used by image manifests
+fn host_architecture() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "powerpc64" => "ppc64le",
+        arch => arch,
+    }
+}
+
+/// Digest of the host platform image from manifest list
+fn platform_digest(manifest: &str) -> Result<String, String> {
+    let manifest: Value = match serde_json::from_str(manifest) {
+        Ok(m) => m,
+        Err(e) => return Err(format!("Unable to parse manifest: {e}")),
+    };
+    let manifests = match manifest["manifests"].as_array() {
+        Some(m) => m,
+        None => return Err(
+            "image is not a manifest list and is not available locally, pull it to resolve digest"
+                .to_owned(),
+        ),
+    };
+    let arch = host_architecture();
+    manifests
+        .iter()
+        .find(|m| m["platform"]["os"] == "linux" && m["platform"]["architecture"] == arch)
+        .and_then(|m| m["digest"].as_str())
+        .map(|d| d.to_owned())
+        .ok_or_else(|| format!("manifest list has no linux/{arch} image"))
+}
+
+/// Exit code of finished container process, failing when it did not exit with zero
+fn container_exit(status: ExitStatus) -> Result<Option<i32>, WorkflowError> {
+    match status.code() {
+        Some(0) => Ok(Some(0)),
+        Some(code) => Err(WorkflowError::ContainerRun(format!(
+            "exited with code {code}"
+        ))),
+        None => Err(WorkflowError::ContainerRun(format!(
+            "terminated by {status}"
+        ))),
+    }
+}
+
+/// Log lines of piped output of container process prefixed by `[job/container]`
+///
+/// Each line is logged as a whole, so output of containers running at once does not
+/// interleave within lines. Output which is not piped is left alone.
+fn forward_output(child: &mut Child, prefix: &str) -> Vec<JoinHandle<()>> {
+    let mut readers: Vec<Box<dyn Read + Send>> = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        readers.push(Box::new(stdout));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        readers.push(Box::new(stderr));
+    }
+    readers
+        .into_iter()
+        .map(|reader| {
+            let prefix = prefix.to_owned();
+            thread::spawn(move || {
+                let mut reader = BufReader::new(reader);
+                let mut line = Vec::new();
+                while let Ok(n) = reader.read_until(b'\n', &mut line) {
+                    if n == 0 {
+                        break;
+                    }
+                    let text = String::from_utf8_lossy(&line);
+                    info!("[{prefix}] {}", text.trim_end_matches(['\n', '\r']));
+                    line.clear();
+                }
+            })
+        })
+        .collect()
+}
+
+/// Wait until all output of finished container process is logged
+fn join_forwarders(forwarders: Vec<JoinHandle<()>>) {
+    for forwarder in forwarders {
+        let _ = forwarder.join();
+    }
+}
+
+/// Environment arguments in configured order
+fn env_args(env: &LinkedHashMap<String, String>, opts: &WorkflowOptions) -> Vec<String> {
+    let mut env: Vec<_> = env.iter().collect();
+    if opts.env_order == EnvOrder::Sorted {
+        env.sort_unstable();
+    }
+    env.into_iter()
+        .map(|(k, v)| format!("--env={}={}", k, v))
+        .collect()
+}
+
+/// Whether network is a named network rather than one of network modes,
+/// only named networks support aliases
+fn user_network(network: &str) -> bool {
+    const MODES: [&str; 8] = [
+        HOST_NETWORK,
+        "none",
+        "bridge",
+        "podman",
+        "private",
+        "slirp4netns",
+        "pasta",
+        "default",
+    ];
+    !MODES.contains(&network) && !network.contains(':')
+}
+
+/// Digest image reference is pinned to, e.g. `sha256:...` of `image@sha256:...`
+fn pinned_digest(image: &str) -> Option<&str> {
+    match image.rsplit_once('@') {
+        Some((_, digest)) if digest.contains(':') => Some(digest),
+        _ => None,
+    }
+}
+
+impl Podman {
+    /// Check that local image pinned to a digest has this digest
+    fn verify_digest(&self, image: &str, opts: &WorkflowOptions) -> Result<(), WorkflowError> {
+        let pinned = match pinned_digest(image) {
+            Some(d) => d,
+            None => return Ok(()),
+        };
+
+        let mut podman = runtime_command(opts);
+        let cmd = podman.args([
+            "image",
+            "inspect",
+            "--format={{range .RepoDigests}}{{println .}}{{end}}",
+            "--",
+            image,
+        ]);
+        log_command(cmd, opts);
+        if opts.dry_run {
+            return Ok(());
+        }
+        let output = match cmd.output() {
+            Ok(o) if o.status.success() => o,
+            Ok(o) => {
+                return Err(WorkflowError::ImagePull(format!(
+                    "{} image inspect exited with {}",
+                    opts.runtime.binary(),
+                    o.status
+                )))
+            }
+            Err(e) => return Err(WorkflowError::ImagePull(e.to_string())),
+        };
+        // Repository digests are in `<repository>@<digest>` format
+        let digests = String::from_utf8_lossy(&output.stdout);
+        let digests: Vec<&str> = digests
+            .lines()
+            .filter_map(|d| d.trim().rsplit_once('@'))
+            .map(|(_, d)| d)
+            .collect();
+        if !digests.contains(&pinned) {
+            return Err(WorkflowError::ImageUnavailable(format!(
+                "Image {image} does not match its pinned digest, local image has {}",
+                if digests.is_empty() {
+                    "no digest".to_owned()
+                } else {
+                    digests.join(", ")
+                }
+            )));
+        }
+        debug!("Image {image} matches its pinned digest");
+        Ok(())
+    }
+
+    /// Check whether local image is older than allowed by --max-image-age
+    fn image_expired(&self, image: &str, opts: &WorkflowOptions) -> Result<bool, String> {
+        let max_age = match opts.max_image_age {
+            Some(a) => a,
+            None => return Ok(false),
+        };
+
+        let mut podman = runtime_command(opts);
+        let cmd = podman.args([
+            "image",
+            "inspect",
+            opts.runtime.created_format(),
+            "--",
+            image,
+        ]);
+        log_command(cmd, opts);
+        if opts.dry_run {
+            return Ok(false);
+        }
+        let output = match cmd.output() {
+            Ok(o) if o.status.success() => o,
+            Ok(o) => {
+                return Err(format!(
+                    "{} image inspect exited with {}",
+                    opts.runtime.binary(),
+                    o.status
+                ))
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+        let created = String::from_utf8_lossy(&output.stdout);
+        let created = match opts.runtime.parse_created(created.trim()) {
+            Ok(c) => c,
+            Err(e) => {
+                return Err(format!(
+                    "Unable to parse creation time of image {image}: {e}"
+                ))
+            }
+        };
+        let age = SystemTime::now()
+            .duration_since(created)
+            .unwrap_or_default();
+        if age > max_age {
+            debug!("Image {image} is {}s old, pulling again", age.as_secs());
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Kill processes started by exec in idle container, keeping the container running
+    fn kill_exec_processes(&self, name: &str, opts: &WorkflowOptions) -> Result<(), String> {
+        let mut podman = runtime_command(opts);
+        // Signal to -1 reaches every process except the idle init process and kill itself
+        let cmd = podman
+            .args(["exec", "--", name, KILL_SHELL, "-c", "kill -KILL -1"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        log_command(cmd, opts);
+        match cmd.status() {
+            Ok(status) if !status.success() => Err(format!(
+                "{} exec kill exited with {status}",
+                opts.runtime.binary()
+            )),
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Wait for container process, terminating it when timeout expires
+    ///
+    /// Job containers are terminated as set by `--timeout-action`, processes of
+    /// timed out exec are killed while their container keeps running.
+    fn wait_with_timeout(
+        &self,
+        name: &str,
+        mut child: Child,
+        timeout: Duration,
+        exec: bool,
+        opts: &WorkflowOptions,
+    ) -> Result<Option<i32>, WorkflowError> {
+        let start = Instant::now();
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) => return container_exit(status),
+                Ok(None) => {}
+                Err(e) => return Err(WorkflowError::ContainerRun(e.to_string())),
+            }
+            if start.elapsed() >= timeout {
+                break;
+            }
+            sleep(Duration::from_millis(100));
+        }
+
+        let terminated = match (exec, opts.timeout_action) {
+            (true, _) => self.kill_exec_processes(name, opts),
+            (false, TimeoutAction::Stop) => self.stop_container(name, opts),
+            (false, TimeoutAction::Kill) => self.kill_container(name, opts),
+        };
+        if let Err(e) = terminated {
+            error!("Unable to terminate timed out container {name}: {e}");
+        }
+        let _ = child.wait();
+        Err(WorkflowError::Timeout(format!(
+            "timed out after {}s",
+            timeout.as_secs()
+        )))
+    }
+}
+
+/// Write input to standard input of the command from own thread, so command output
+/// filling its pipes does not block the writing, and close it once written
+fn write_input(child: &mut Child, input: &str) -> JoinHandle<()> {
+    let mut stdin = child.stdin.take().expect("Command stdin is piped");
+    let input = input.to_owned();
+    thread::spawn(move || match stdin.write_all(input.as_bytes()) {
+        // Command does not have to read all of it
+        Err(e) if e.kind() != ErrorKind::BrokenPipe => {
+            warn!("Unable to write input of command: {e}")
+        }
+        _ => {}
+    })
+}
+
+/// Run image pull, killing it when it does not finish in time
+fn wait_pull(mut child: Child, image: &str, timeout: Duration) -> Result<ExitStatus, String> {
+    let start = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Ok(status),
+            Ok(None) => {}
+            Err(e) => return Err(e.to_string()),
+        }
+        if start.elapsed() >= timeout {
+            break;
+        }
+        sleep(Duration::from_millis(100));
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+    Err(format!(
+        "image pull of {image} timed out after {}s",
+        timeout.as_secs()
+    ))
+}
+
+/// Failed image pull
+struct PullFailure {
+    message: String,
+    /// Pull may succeed when tried again, e.g. registry was overloaded
+    retryable: bool,
+}
+
+/// Registry errors which do not go away by pulling again
+const PERMANENT_PULL_ERRORS: [&str; 8] = [
+    "manifest unknown",
+    "name unknown",
+    "not found",
+    "unauthorized",
+    "authentication required",
+    "denied",
+    "invalid reference",
+    "repository does not exist",
+];
+
+/// Whether pull error output tells the image can not be pulled at all
+fn permanent_pull_error(output: &str) -> bool {
+    let output = output.to_lowercase();
+    PERMANENT_PULL_ERRORS.iter().any(|e| output.contains(e))
+}
+
+/// Delay before next pull attempt, doubling with every failed attempt, randomized to
+/// between half and full delay so machines installed together do not retry at once
+fn pull_retry_delay(base: Duration, attempt: u32) -> Duration {
+    let delay = base.saturating_mul(1 << (attempt - 1).min(10));
+    let random = RandomState::new().build_hasher().finish();
+    delay.mul_f64(0.5 + (random % 1000) as f64 / 2000.0)
+}
+
+impl Podman {
+    /// Pull image once, error output is logged and kept to classify failure
+    fn pull_image(&self, image: &str, opts: &WorkflowOptions) -> Result<(), PullFailure> {
+        let mut podman = runtime_command(opts);
+        let mut cmd = podman
+            .args(["image", "pull"])
+            .args(opts.runtime.tls_args("pull"));
+        if let Some(authfile) = &opts.authfile {
+            cmd = cmd.arg(format!("--authfile={}", authfile.display()));
+        }
+        cmd = cmd.args(["--", image]).stderr(Stdio::piped());
+
+        log_command(cmd, opts);
+        if opts.dry_run {
+            return Ok(());
+        }
+        let failure = |message, retryable| PullFailure { message, retryable };
+        let mut child = cmd.spawn().map_err(|e| failure(e.to_string(), false))?;
+        let stderr = child.stderr.take().expect("Pull stderr is piped");
+        let prefix = format!("pull {image}");
+        let errors = thread::spawn(move || {
+            let mut output = String::new();
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                // Logged as container output, so secrets are masked
+                info!("[{prefix}] {line}");
+                output.push_str(&line);
+                output.push('\n');
+            }
+            output
+        });
+        let status = match opts.pull_timeout {
+            Some(timeout) => wait_pull(child, image, timeout),
+            None => child.wait().map_err(|e| e.to_string()),
+        };
+        let output = errors.join().expect("Pull output reader panicked");
+        match status {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(failure(
+                format!("{} image pull exited with {status}", opts.runtime.binary()),
+                !permanent_pull_error(&output),
+            )),
+            // Timed out pull
+            Err(e) => Err(failure(e, true)),
+        }
+    }
+}
+
 impl ImageOps for Podman {
-    fn prepare_image(&self, image: &str, dry_run: bool) -> Result<(), String> {
-        let mut podman = Command::new("podman");
-        let cmd = podman.args(["image", "pull", "--tls-verify=false", "--", image]);
+    fn prepare_image(
+        &self,
+        image: &str,
+        policy: PullPolicy,
+        opts: &WorkflowOptions,
+    ) -> Result<(), WorkflowError> {
+        match policy {
+            PullPolicy::Always => {}
+            PullPolicy::Missing => {
+                if is_pulled(image) {
+                    debug!("Image {image} was already pulled by this run");
+                    return Ok(());
+                }
+                if self
+                    .image_exists(image, opts)
+                    .map_err(WorkflowError::ImagePull)?
+                    && !self
+                        .image_expired(image, opts)
+                        .map_err(WorkflowError::ImagePull)?
+                {
+                    debug!("Image {image} is present, not pulling");
+                    self.verify_digest(image, opts)?;
+                    set_pulled(image, true);
+                    return Ok(());
+                }
+            }
+            PullPolicy::Never => {
+                if !self
+                    .image_exists(image, opts)
+                    .map_err(WorkflowError::ImagePull)?
+                {
+                    return Err(WorkflowError::ImageUnavailable(format!(
+                        "Image {image} is not available locally and pulling is disabled"
+                    )));
+                }
+                return self.verify_digest(image, opts);
+            }
+        }
 
-        debug!("{cmd:?}");
-        if !dry_run {
-            if let Err(e) = cmd.status() {
-                return Err(e.to_string());
+        let mut attempt = 1;
+        while let Err(failure) = self.pull_image(image, opts) {
+            if !failure.retryable {
+                return Err(WorkflowError::ImageUnavailable(failure.message));
             }
+            if attempt >= opts.pull_attempts {
+                return Err(WorkflowError::ImagePull(failure.message));
+            }
+            let delay = pull_retry_delay(opts.pull_retry_delay, attempt);
+            warn!(
+                "Pull of image {image} failed: {}, attempt {attempt} of {}, retrying in {:.1}s",
+                failure.message,
+                opts.pull_attempts,
+                delay.as_secs_f64()
+            );
+            sleep(delay);
+            attempt += 1;
         }
+        self.verify_digest(image, opts)?;
+        set_pulled(image, true);
         Ok(())
     }
 
+    /// Check whether image is present in local storage
+    fn image_exists(&self, image: &str, opts: &WorkflowOptions) -> Result<bool, String> {
+        let mut podman = runtime_command(opts);
+        let cmd = podman
+            .args(opts.runtime.exists_args("image"))
+            .args(["--", image])
+            .stdout(Stdio::null());
+        log_command(cmd, opts);
+        if !opts.dry_run {
+            match cmd.status() {
+                Ok(status) => return Ok(status.success()),
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Local images are resolved from local storage, remote ones from their manifest list
+    ///
+    /// Only inspects images, so it runs also in dry-run mode.
+    fn image_digest(&self, image: &str, opts: &WorkflowOptions) -> Result<String, String> {
+        let mut podman = runtime_command(opts);
+        let cmd = podman.args([
+            "image",
+            "inspect",
+            opts.runtime.digest_format(),
+            "--",
+            image,
+        ]);
+        log_command(cmd, opts);
+        match cmd.stderr(Stdio::null()).output() {
+            Ok(o) if o.status.success() => {
+                // Repository digest is in `<repository>@<digest>` format
+                let digest = String::from_utf8_lossy(&o.stdout);
+                let digest = digest.trim().rsplit('@').next().unwrap_or_default();
+                return Ok(digest.to_owned());
+            }
+            Ok(_) => {}
+            Err(e) => return Err(e.to_string()),
+        }
+
+        let mut podman = runtime_command(opts);
+        let mut cmd = podman
+            .args(["manifest", "inspect"])
+            .args(opts.runtime.tls_args("manifest"));
+        if let Some(authfile) = &opts.authfile {
+            cmd = cmd.arg(format!("--authfile={}", authfile.display()));
+        }
+        cmd = cmd.args(["--", image]);
+        log_command(cmd, opts);
+        match cmd.output() {
+            Ok(o) if o.status.success() => platform_digest(&String::from_utf8_lossy(&o.stdout)),
+            Ok(o) => Err(String::from_utf8_lossy(&o.stderr).trim().to_owned()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
     /// Clean container images
     fn clean_image(&self, image: &str, opts: &WorkflowOptions) -> Result<(), String> {
-        if opts.debug {
-            debug!("Not cleaning job image {image} because of debug option");
+        if !opts.cleanup {
+            debug!("Not cleaning job image {image} because cleanup is disabled");
             return Ok(());
         }
 
-        let mut podman = Command::new("podman");
+        let mut podman = runtime_command(opts);
         let cmd = podman.args(["image", "rm", "--force", "--", image]);
-        debug!("{cmd:?}");
+        log_command(cmd, opts);
+        set_pulled(image, false);
         if !opts.dry_run {
             if let Err(e) = cmd.status() {
                 return Err(e.to_string());
@@ -43,9 +728,12 @@ impl ImageOps for Podman {
 
 impl VolumeOps for Podman {
     fn prepare_volume(&self, name: &str, opts: &WorkflowOptions) -> Result<(), String> {
-        let mut podman = Command::new("podman");
-        let cmd = podman.args(["volume", "exists", name]);
-        debug!("{cmd:?}");
+        let mut podman = runtime_command(opts);
+        let cmd = podman
+            .args(opts.runtime.exists_args("volume"))
+            .args(["--", name])
+            .stdout(Stdio::null());
+        log_command(cmd, opts);
         if !opts.dry_run {
             match cmd.status() {
                 Ok(status) => {
@@ -59,88 +747,390 @@ impl VolumeOps for Podman {
             }
         }
 
-        let mut podman = Command::new("podman");
-        let cmd = podman.args(["volume", "create", name]);
-        debug!("{cmd:?}");
+        let mut podman = runtime_command(opts);
+        let cmd = podman.args(["volume", "create", "--label=iguana=true", "--", name]);
+        log_command(cmd, opts);
         if !opts.dry_run {
-            if let Err(e) = cmd.status() {
-                return Err(e.to_string());
+            match cmd.status() {
+                Ok(status) if !status.success() => {
+                    return Err(format!(
+                        "{} volume create exited with {status}",
+                        opts.runtime.binary()
+                    ));
+                }
+                Ok(_) => {}
+                Err(e) => return Err(e.to_string()),
             }
         }
         Ok(())
     }
 
     fn clean_volumes(&self, volumes: &HashSet<&str>, opts: &WorkflowOptions) -> Result<(), String> {
-        let mut podman = Command::new("podman");
-        let mut cmd = podman.args(["volume", "remove"]);
-        cmd = cmd.args(volumes);
-        debug!("{cmd:?}");
+        if !opts.cleanup || opts.keep_volumes {
+            debug!(
+                "Not removing volumes {volumes:?} because cleanup is disabled or volumes are kept"
+            );
+            return Ok(());
+        }
+
+        // Only volumes created by iguana are removed, not pre-existing ones used by the workflow
+        let mut created = volumes.clone();
         if !opts.dry_run {
-            if let Err(e) = cmd.status() {
-                return Err(e.to_string());
+            let mut podman = runtime_command(opts);
+            let cmd = podman.args([
+                "volume",
+                "ls",
+                "--filter=label=iguana=true",
+                "--format={{.Name}}",
+            ]);
+            log_command(cmd, opts);
+            let output = match cmd.output() {
+                Ok(o) if o.status.success() => o,
+                Ok(o) => {
+                    return Err(format!(
+                        "{} volume ls exited with {}",
+                        opts.runtime.binary(),
+                        o.status
+                    ))
+                }
+                Err(e) => return Err(e.to_string()),
+            };
+            let labeled = String::from_utf8_lossy(&output.stdout);
+            let labeled: HashSet<&str> = labeled.lines().collect();
+            created.retain(|v| labeled.contains(v));
+        }
+        if created.is_empty() {
+            return Ok(());
+        }
+
+        let mut podman = runtime_command(opts);
+        let cmd = podman.args(["volume", "rm", "--"]).args(created);
+        log_command(cmd, opts);
+        if !opts.dry_run {
+            match cmd.status() {
+                Ok(status) if !status.success() => {
+                    return Err(format!(
+                        "{} volume rm exited with {status}",
+                        opts.runtime.binary()
+                    ));
+                }
+                Ok(_) => {}
+                Err(e) => return Err(e.to_string()),
             }
         }
         Ok(())
     }
 }
 
-impl ContainerOps for Podman {
-    fn run_container(
-        &self,
-        container: &Container,
-        is_service: bool,
-        env: HashMap<String, String>,
-        opts: &WorkflowOptions,
-    ) -> Result<(), String> {
-        // Prepare volumes if specified
-        let mut volumes = Vec::new();
-        if container.volumes.is_some() {
-            for v in container.volumes.as_ref().unwrap() {
-                let src = v.split(":").take(1).collect::<Vec<_>>()[0];
-                match self.prepare_volume(src, opts) {
-                    Ok(()) => {}
-                    Err(e) => {
-                        return Err(e);
-                    }
+/// Runtime command running the container, without its output redirected
+fn run_command(run: &ContainerRun, opts: &WorkflowOptions) -> Result<Command, WorkflowError> {
+    let container = run.container;
+    let mut volumes = Vec::new();
+    if let Some(c_volumes) = &container.volumes {
+        for v in c_volumes {
+            // Relative host directories are relative to the workflow context
+            let v = match v.split_once(':') {
+                Some((src, rest)) if src.starts_with('.') => {
+                    format!("{}:{rest}", opts.resolve_path(src).display())
                 }
-                volumes.push(format!("--volume={v}"));
+                _ => v.to_owned(),
+            };
+            volumes.push(format!("--volume={v}"));
+        }
+    }
+    // Ephemeral in-memory mounts
+    let mut tmpfs = Vec::new();
+    if let Some(c_tmpfs) = &container.tmpfs {
+        for t in c_tmpfs {
+            tmpfs.push(format!("--tmpfs={t}"));
+        }
+    }
+    // Host devices passed to the container without full privileged mode
+    let mut devices = Vec::new();
+    if let Some(c_devices) = &container.devices {
+        for d in c_devices {
+            // Format is checked with the workflow, device may be missing on this host
+            let host = d.split(':').next().unwrap_or_default();
+            if !Path::new(host).exists() {
+                return Err(WorkflowError::ContainerRun(format!(
+                    "Device {host} does not exist on host"
+                )));
             }
+            devices.push(format!("--device={d}"));
         }
-        // Run the container
-        let mut podman = Command::new("podman");
-        let mut cmd = podman.args([
-            "run",
-            "--network=host",
-            "--annotation=iguana=true",
-            "--env=iguana=true",
-            "--mount=type=bind,source=/iguana,target=/iguana",
+    }
+    let mut podman = runtime_command(opts);
+    let mut cmd = podman
+        .args(["run", &format!("--name={}", run.name)])
+        .args(opts.runtime.marker_args())
+        .arg(format!(
+            "--network={}",
+            container.network.as_deref().unwrap_or(HOST_NETWORK)
+        ))
+        .arg("--env=iguana=true");
+    if let Some(iguana_dir) = &opts.iguana_dir {
+        cmd = cmd.arg(format!(
+            "--mount=type=bind,source={},target={IGUANA_TARGET}",
+            iguana_dir.display()
+        ));
+    }
+    if !opts.run_id.is_empty() {
+        cmd = cmd.args([
+            format!("--label=iguana.run={}", opts.run_id),
+            format!("--env=IGUANA_RUN_ID={}", opts.run_id),
         ]);
+    }
 
-        if opts.privileged {
-            cmd = cmd.args(["--volume=/dev:/dev", "--privileged"]);
+    if let Some(hostname) = &container.hostname {
+        cmd = cmd.arg(format!("--hostname={hostname}"));
+        // Other containers on the same user defined network resolve it by hostname
+        if container.network.as_deref().is_some_and(user_network) {
+            cmd = cmd.arg(format!("--network-alias={hostname}"));
         }
+    }
+    for (host, ip) in container.extra_hosts.iter().flatten() {
+        cmd = cmd.arg(format!("--add-host={host}:{ip}"));
+    }
+
+    if let Some(newroot) = &opts.newroot {
+        cmd = cmd.args([
+            format!(
+                "--mount=type=bind,source={},target={NEWROOT_TARGET}",
+                newroot.display()
+            ),
+            format!("--env=IGUANA_NEWROOT={NEWROOT_TARGET}"),
+        ]);
+    }
+
+    if let Some(workflow_dir) = &opts.workflow_dir {
+        cmd = cmd.args([
+            format!(
+                "--mount=type=bind,source={},target={WORKFLOW_DIR_TARGET},readonly",
+                workflow_dir.display()
+            ),
+            format!("--env=IGUANA_WORKFLOW_DIR={WORKFLOW_DIR_TARGET}"),
+        ]);
+    }
+
+    if let Some(output) = run.output {
+        cmd = cmd.args([
+            format!(
+                "--mount=type=bind,source={},target={OUTPUT_TARGET}",
+                output.display()
+            ),
+            format!("--env=IGUANA_OUTPUT={OUTPUT_TARGET}"),
+        ]);
+    }
+
+    if let Some(step_outputs) = run.step_outputs {
+        cmd = cmd.arg(format!(
+            "--mount=type=bind,source={},target={STEP_OUTPUTS_TARGET}",
+            step_outputs.display()
+        ));
+    }
+
+    if opts.privileged {
+        cmd = cmd.args(["--volume=/dev:/dev", "--privileged"]);
+    }
+
+    if opts.effective_pull_policy(container) == PullPolicy::Never {
+        cmd = cmd.arg("--pull=never");
+    }
+
+    if let Some(authfile) = &opts.authfile {
+        cmd = cmd.arg(format!("--authfile={}", authfile.display()));
+    }
+
+    for (k, v) in opts.labels.iter() {
+        cmd = cmd.arg(format!("--label={k}={v}"));
+    }
+    for (k, v) in container.labels.iter().flatten() {
+        cmd = cmd.arg(format!("--label={k}={v}"));
+    }
+
+    if !volumes.is_empty() {
+        cmd = cmd.args(volumes);
+    }
+
+    if !tmpfs.is_empty() {
+        cmd = cmd.args(tmpfs);
+    }
+
+    if !devices.is_empty() {
+        cmd = cmd.args(devices);
+    }
+
+    if let Some(gpus) = &container.gpus {
+        cmd = cmd.arg(format!("--gpus={gpus}"));
+    }
+
+    if let Some(memory) = container.memory.as_ref().or(opts.memory.as_ref()) {
+        cmd = cmd.arg(format!("--memory={memory}"));
+    }
+
+    if let Some(cpus) = container.cpus.or(opts.cpus) {
+        cmd = cmd.arg(format!("--cpus={cpus}"));
+    }
+
+    for port in container.ports.iter().flatten() {
+        cmd = cmd.arg(format!("--publish={port}"));
+    }
+
+    if let Some(dir) = &container.working_directory {
+        cmd = cmd.arg(format!("--workdir={dir}"));
+    }
 
-        if !volumes.is_empty() {
-            cmd = cmd.args(volumes);
+    if let Some(user) = &container.user {
+        cmd = cmd.arg(format!("--user={user}"));
+    }
+
+    if let Some(shell) = run.shell {
+        cmd = cmd.args(["--tty", "--interactive", &format!("--entrypoint={shell}")]);
+    } else if run.idle {
+        cmd = cmd.args(["--detach", "--entrypoint=tail"]);
+    } else {
+        // Empty entrypoint clears the one from image, absent one keeps it
+        match container.entrypoint.as_deref() {
+            Some("") => cmd = cmd.arg("--entrypoint="),
+            Some(entrypoint) => cmd = cmd.arg(format!("--entrypoint={entrypoint}")),
+            None => {}
         }
 
-        if is_service {
+        if run.detach {
             cmd = cmd.arg("--detach");
-        } else {
+        } else if run.log.is_none() {
             cmd = cmd.arg("--interactive");
         }
+    }
+
+    if let Some(restart) = container.restart {
+        cmd = cmd.arg(format!("--restart={}", restart.as_str()));
+    }
+
+    // Restarted containers are removed by cleanup after they are stopped
+    if opts.cleanup && !run.keep && !container.restarts() {
+        cmd = cmd.arg("--rm");
+    }
+
+    cmd = cmd.args(env_args(&run.env, opts));
+
+    cmd = cmd.args(["--", run.image]);
+    // Idle process waiting forever
+    if run.idle {
+        cmd.args(["-f", "/dev/null"]);
+    } else if let (None, Some(command)) = (run.shell, &container.command) {
+        cmd.args(command);
+    }
+    Ok(podman)
+}
 
-        if !opts.debug {
-            cmd = cmd.arg("--rm");
+impl ContainerOps for Podman {
+    fn run_container(
+        &self,
+        run: ContainerRun,
+        opts: &WorkflowOptions,
+    ) -> Result<Option<i32>, WorkflowError> {
+        let name = run.name;
+        let container = run.container;
+        // Prepare volumes if specified
+        for src in container.named_volumes() {
+            self.prepare_volume(src, opts)
+                .map_err(WorkflowError::ContainerRun)?;
+        }
+        // Run the container
+        if opts.runtime == Runtime::Docker {
+            self.remove_container(name, opts)
+                .map_err(WorkflowError::ContainerRun)?;
+        }
+        let mut podman = run_command(&run, opts)?;
+        let mut cmd = &mut podman;
+        log_command(cmd, opts);
+        if !opts.dry_run {
+            // Shell stays attached to the terminal, detached containers print nothing
+            let forward = run.log.is_none() && run.shell.is_none() && !run.detach && !run.idle;
+            if let Some(log) = run.log {
+                let (stdout, stderr) = log_stdio(log).map_err(WorkflowError::ContainerRun)?;
+                cmd = cmd.stdout(stdout).stderr(stderr);
+            } else if forward {
+                cmd = cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+            }
+            interrupt::track(name).map_err(WorkflowError::ContainerRun)?;
+            let mut child = match cmd.spawn() {
+                Ok(c) => c,
+                Err(e) => return Err(WorkflowError::ContainerRun(e.to_string())),
+            };
+            let forwarders = forward_output(&mut child, run.prefix);
+            let result = match run.timeout {
+                Some(t) if !run.detach => self.wait_with_timeout(name, child, t, false, opts),
+                _ => match child.wait() {
+                    Ok(status) => container_exit(status),
+                    Err(e) => Err(WorkflowError::ContainerRun(e.to_string())),
+                },
+            };
+            join_forwarders(forwarders);
+            return result;
         }
+        Ok(None)
+    }
 
-        for (k, v) in env.iter() {
-            cmd.arg(format!("--env={}={}", k, v));
+    fn stop_container(&self, name: &str, opts: &WorkflowOptions) -> Result<(), String> {
+        let mut podman = runtime_command(opts);
+        let cmd = podman
+            .args(["container", "stop"])
+            .args(opts.runtime.ignore_args())
+            .args(opts.stop_timeout.map(|t| format!("--time={t}")))
+            .args(["--", name])
+            .stdout(Stdio::null());
+        log_command(cmd, opts);
+        if opts.dry_run {
+            return Ok(());
+        }
+        let stopped = match cmd.output() {
+            Ok(o) if !o.status.success() && !missing_container(&o.stderr) => Err(format!(
+                "{} stop exited with {}: {}",
+                opts.runtime.binary(),
+                o.status,
+                String::from_utf8_lossy(&o.stderr).trim()
+            )),
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        };
+        // Container ignoring the stop signal may outlive failed stop
+        if container_running(name, opts) {
+            warn!("Container {name} is still running, killing it");
+            return self.kill_container(name, opts);
         }
+        stopped
+    }
 
-        cmd = cmd.args(["--", &container.image]);
+    fn kill_container(&self, name: &str, opts: &WorkflowOptions) -> Result<(), String> {
+        let mut podman = runtime_command(opts);
+        let cmd = podman.args(["container", "kill", "--", name]);
+        log_command(cmd, opts);
+        if !opts.dry_run {
+            match cmd.status() {
+                Ok(status) if !status.success() => {
+                    return Err(format!(
+                        "{} kill exited with {status}",
+                        opts.runtime.binary()
+                    ));
+                }
+                Ok(_) => {}
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        Ok(())
+    }
 
-        debug!("{cmd:?}");
+    fn remove_container(&self, name: &str, opts: &WorkflowOptions) -> Result<(), String> {
+        let mut podman = runtime_command(opts);
+        let cmd = podman
+            .args(["container", "rm", "--force"])
+            .args(opts.runtime.ignore_args())
+            .args(["--", name])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        log_command(cmd, opts);
         if !opts.dry_run {
             if let Err(e) = cmd.status() {
                 return Err(e.to_string());
@@ -149,15 +1139,264 @@ impl ContainerOps for Podman {
         Ok(())
     }
 
-    fn stop_container(&self, name: &str, opts: &WorkflowOptions) -> Result<(), String> {
-        let mut podman = Command::new("podman");
-        let cmd = podman.args(["container", "stop", "--ignore", "--", name]);
-        debug!("{cmd:?}");
+    /// Create image from container filesystem
+    fn commit_container(
+        &self,
+        name: &str,
+        image: &str,
+        opts: &WorkflowOptions,
+    ) -> Result<(), String> {
+        let mut podman = runtime_command(opts);
+        let cmd = podman.args(["container", "commit", "--", name, image]);
+        log_command(cmd, opts);
         if !opts.dry_run {
-            if let Err(e) = cmd.status() {
-                return Err(e.to_string());
+            match cmd.status() {
+                Ok(status) if !status.success() => {
+                    return Err(format!(
+                        "{} commit exited with {status}",
+                        opts.runtime.binary()
+                    ));
+                }
+                Ok(_) => {}
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Run healthcheck command inside of running container
+    fn check_health(&self, name: &str, cmd: &str, opts: &WorkflowOptions) -> Result<bool, String> {
+        let mut podman = runtime_command(opts);
+        let cmd = podman
+            .args(["exec", "--", name, "/bin/sh", "-c", cmd])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        log_command(cmd, opts);
+        if !opts.dry_run {
+            match cmd.status() {
+                Ok(status) => return Ok(status.success()),
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        Ok(true)
+    }
+
+    fn dir_exists(&self, name: &str, path: &str, opts: &WorkflowOptions) -> Result<bool, String> {
+        let mut podman = runtime_command(opts);
+        let cmd = podman
+            .args(["exec", "--", name, "test", "-d", path])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        log_command(cmd, opts);
+        if !opts.dry_run {
+            match cmd.status() {
+                Ok(status) => return Ok(status.success()),
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        Ok(true)
+    }
+
+    fn shell_exists(
+        &self,
+        name: &str,
+        shell: &str,
+        opts: &WorkflowOptions,
+    ) -> Result<bool, String> {
+        let mut podman = runtime_command(opts);
+        let cmd = podman
+            .args(["exec", "--", name, shell, "-c", "true"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        log_command(cmd, opts);
+        if !opts.dry_run {
+            match cmd.status() {
+                Ok(status) => return Ok(status.success()),
+                Err(e) => return Err(e.to_string()),
             }
         }
+        Ok(true)
+    }
+
+    fn exec_in_container(
+        &self,
+        name: &str,
+        exec: ContainerExec,
+        opts: &WorkflowOptions,
+    ) -> Result<Option<i32>, WorkflowError> {
+        let mut podman = runtime_command(opts);
+        let mut cmd = podman.arg("exec").args(env_args(exec.env, opts));
+        if let Some(dir) = exec.workdir {
+            cmd = cmd.arg(format!("--workdir={dir}"));
+        }
+        if exec.input.is_some() {
+            cmd = cmd.arg("--interactive").stdin(Stdio::piped());
+        }
+        let mut cmd = cmd.args(["--", name, exec.shell, "-c", exec.cmd]);
+        log_command(cmd, opts);
+        if opts.dry_run {
+            return Ok(None);
+        }
+        if let Some(log) = exec.log {
+            let (stdout, stderr) = log_stdio(log).map_err(WorkflowError::ContainerRun)?;
+            cmd = cmd.stdout(stdout).stderr(stderr);
+        } else {
+            cmd = cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+        let mut child = match cmd.spawn() {
+            Ok(c) => c,
+            Err(e) => return Err(WorkflowError::ContainerRun(e.to_string())),
+        };
+        let forwarders = forward_output(&mut child, exec.prefix);
+        let writer = exec.input.map(|input| write_input(&mut child, input));
+        let result = match exec.timeout {
+            Some(t) => self.wait_with_timeout(name, child, t, true, opts),
+            None => match child.wait() {
+                Ok(status) => container_exit(status),
+                Err(e) => Err(WorkflowError::ContainerRun(e.to_string())),
+            },
+        };
+        join_forwarders(forwarders);
+        if let Some(writer) = writer {
+            writer.join().expect("Input writer panicked");
+        }
+        result
+    }
+
+    fn copy_from_container(
+        &self,
+        name: &str,
+        src: &str,
+        dest: &Path,
+        opts: &WorkflowOptions,
+    ) -> Result<(), String> {
+        let mut podman = runtime_command(opts);
+        let cmd = podman
+            .args(["container", "cp", "--", &format!("{name}:{src}")])
+            .arg(dest);
+        log_command(cmd, opts);
+        if !opts.dry_run {
+            match cmd.status() {
+                Ok(status) if !status.success() => {
+                    return Err(format!("{} cp exited with {status}", opts.runtime.binary()));
+                }
+                Ok(_) => {}
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        Ok(())
+    }
+
+    fn follow_logs(&self, name: &str, path: &Path, opts: &WorkflowOptions) -> Result<(), String> {
+        let mut podman = runtime_command(opts);
+        let cmd = podman.args(["logs", "--follow", "--timestamps", "--", name]);
+        match opts.dry_run {
+            true => info!("{cmd:?} > {}", path.display()),
+            false => debug!("{cmd:?} > {}", path.display()),
+        }
+        if opts.dry_run {
+            return Ok(());
+        }
+
+        let file = match File::create(path) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Unable to create log file {}: {e}", path.display())),
+        };
+        let stderr = match file.try_clone() {
+            Ok(f) => f,
+            Err(e) => return Err(e.to_string()),
+        };
+        let mut child = match cmd.stdout(file).stderr(stderr).spawn() {
+            Ok(c) => c,
+            Err(e) => return Err(e.to_string()),
+        };
+        // Runtime writes the file directly, the thread only reaps it once the container stops
+        let name = name.to_owned();
+        thread::spawn(move || match child.wait() {
+            Ok(status) if !status.success() => {
+                error!("Following logs of container {name} failed: {status}")
+            }
+            Err(e) => error!("Following logs of container {name} failed: {e}"),
+            Ok(_) => {}
+        });
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workflow::testing::options;
+    use crate::workflow::Container;
+
+    /// Arguments of runtime command running container given as YAML
+    fn run_args(container: &str, opts: &WorkflowOptions) -> Vec<String> {
+        let container: Container = serde_yaml::from_str(container).unwrap();
+        let run = ContainerRun {
+            name: "iguana-job-main",
+            image: &container.image,
+            container: &container,
+            detach: false,
+            env: LinkedHashMap::new(),
+            timeout: None,
+            keep: false,
+            shell: None,
+            idle: false,
+            output: None,
+            step_outputs: None,
+            log: None,
+            prefix: "job/main",
+        };
+        let cmd = run_command(&run, opts).unwrap();
+        cmd.get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn command_follows_image_as_separate_args() {
+        let args = run_args(
+            "{image: registry.example.com/base:1, command: [echo, hi]}",
+            &options(),
+        );
+        assert_eq!(
+            args[args.len() - 4..],
+            ["--", "registry.example.com/base:1", "echo", "hi"]
+        );
+    }
+
+    #[test]
+    fn user_is_passed_as_given() {
+        let args = run_args(
+            "{image: registry.example.com/base:1, user: \"1000:1000\"}",
+            &options(),
+        );
+        assert!(args.contains(&"--user=1000:1000".to_owned()));
+    }
+
+    #[test]
+    fn marker_and_user_labels_are_set() {
+        let container = "{image: registry.example.com/base:1, labels: {team: infra}}";
+        let mut opts = options();
+        opts.labels = vec![("site".to_owned(), "lab".to_owned())];
+        for (runtime, marker, other) in [
+            (
+                Runtime::Podman,
+                "--annotation=iguana=true",
+                "--label=iguana=true",
+            ),
+            (
+                Runtime::Docker,
+                "--label=iguana=true",
+                "--annotation=iguana=true",
+            ),
+        ] {
+            opts.runtime = runtime;
+            let args = run_args(container, &opts);
+            assert!(args.contains(&marker.to_owned()), "{runtime:?} {args:?}");
+            assert!(!args.contains(&other.to_owned()), "{runtime:?} {args:?}");
+            assert!(args.contains(&"--label=site=lab".to_owned()));
+            assert!(args.contains(&"--label=team=infra".to_owned()));
+        }
+    }
+}
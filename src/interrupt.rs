@@ -0,0 +1,132 @@
+/// Cleanup of started containers when workflow is interrupted by SIGINT or SIGTERM
+/// or aborted after exceeding its timeout
+use log::{error, warn};
+use std::io;
+use std::mem::MaybeUninit;
+use std::process::exit;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::engines::podman::Podman;
+use crate::engines::ContainerOps;
+use crate::workflow::WorkflowOptions;
+
+static STARTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static ABORTED: AtomicBool = AtomicBool::new(false);
+
+/// Remember container about to be started, so it is stopped on interrupt
+///
+/// Blocks while interrupt cleanup runs, so no container is started after it.
+/// Fails once the workflow was aborted.
+pub fn track(name: &str) -> Result<(), String> {
+    let mut started = STARTED.lock().unwrap();
+    if aborted() {
+        return Err(format!(
+            "Not starting container {name}, workflow was aborted"
+        ));
+    }
+    if !started.iter().any(|s| s == name) {
+        started.push(name.to_owned());
+    }
+    Ok(())
+}
+
+/// Whether the workflow was aborted, no more jobs are started then
+pub fn aborted() -> bool {
+    ABORTED.load(Ordering::SeqCst)
+}
+
+/// Forget containers and abort of previous workflow run, before the next one starts
+pub fn reset() {
+    let mut started = STARTED.lock().unwrap();
+    started.clear();
+    ABORTED.store(false, Ordering::SeqCst);
+}
+
+/// Stop and remove all started containers in reverse order
+fn stop_started(started: &[String], opts: &WorkflowOptions) {
+    let engine = Podman;
+    for name in started.iter().rev() {
+        if let Err(e) = engine.stop_container(name, opts) {
+            error!("Stopping of container {name} failed: {e}");
+        }
+        if let Err(e) = engine.remove_container(name, opts) {
+            error!("Removal of container {name} failed: {e}");
+        }
+    }
+}
+
+/// Abort running workflow, stopping all started containers and refusing to start new ones
+///
+/// Jobs whose containers were stopped fail, so the workflow finishes normally afterwards.
+pub fn abort(opts: &WorkflowOptions) {
+    let started = STARTED.lock().unwrap();
+    ABORTED.store(true, Ordering::SeqCst);
+    stop_started(&started, opts);
+}
+
+/// Handle SIGINT and SIGTERM by stopping and removing all started containers and exiting
+///
+/// Signals are blocked in the calling thread and all threads spawned by it later and
+/// waited for by dedicated thread. Must be called before any other thread is spawned.
+/// Spawned processes start with empty signal mask, so they receive the signals as usual.
+pub fn install(opts: WorkflowOptions) -> Result<(), String> {
+    let set = unsafe {
+        let mut set = MaybeUninit::<libc::sigset_t>::uninit();
+        libc::sigemptyset(set.as_mut_ptr());
+        libc::sigaddset(set.as_mut_ptr(), libc::SIGINT);
+        libc::sigaddset(set.as_mut_ptr(), libc::SIGTERM);
+        set.assume_init()
+    };
+    let rc = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut()) };
+    if rc != 0 {
+        return Err(format!(
+            "Unable to install signal handler: {}",
+            io::Error::from_raw_os_error(rc)
+        ));
+    }
+
+    thread::spawn(move || {
+        let mut signal = 0;
+        let rc = unsafe { libc::sigwait(&set, &mut signal) };
+        if rc != 0 {
+            error!(
+                "Waiting for signals failed: {}",
+                io::Error::from_raw_os_error(rc)
+            );
+            return;
+        }
+        warn!("Interrupted by signal {signal}, stopping started containers");
+        // Lock is held until exit, jobs can not start more containers meanwhile
+        let started = STARTED.lock().unwrap();
+        stop_started(&started, &opts);
+        exit(128 + signal);
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workflow::do_workflow;
+    use crate::workflow::testing::{options, serial, StartObserver};
+
+    #[test]
+    fn run_after_aborted_run_starts_jobs() {
+        let _serial = serial();
+        let workflow = "
+jobs:
+  install:
+    container:
+      image: registry.example.com/base:1
+";
+        abort(&options());
+        assert!(track("iguana-install-main").is_err());
+        let observer = StartObserver::default();
+        do_workflow(workflow.to_owned(), &options(), &observer).unwrap();
+        assert_eq!(observer.started(), ["install"]);
+        assert!(!aborted());
+    }
+}
@@ -1,73 +1,948 @@
-use clap::Parser;
-use env_logger::Env;
-use log::{error, info};
+use clap::{Parser, ValueEnum};
+use env_logger::{Env, WriteStyle};
+use linked_hash_map::LinkedHashMap;
+use log::{debug, error, info, warn, LevelFilter};
+use serde_json::Value;
 
+use std::env;
 use std::fs;
-use std::path::Path;
+use std::io::{self, ErrorKind, IsTerminal, Read};
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
 
-use crate::workflow::{do_workflow, WorkflowOptions};
+use crate::secrets::MaskingLogger;
+use crate::workflow::{
+    check_env_names, do_workflow, env_key_problem, parse_cpus, parse_env_file, parse_memory,
+    EnvOrder, JobStatus, OutputFormat, PullPolicy, RunObserver, Runtime, StepStatus, TimeoutAction,
+    WorkflowError, WorkflowFormat, WorkflowOptions,
+};
 
+mod auth;
+mod bundle;
+mod cmdline;
 mod engines;
+mod interrupt;
+mod secrets;
 mod workflow;
 
+/// Workflow argument reading the workflow from standard input
+const STDIN_WORKFLOW: &str = "-";
+
+/// Exit code of successful workflow in which some jobs with `continue_on_error` failed
+const EXIT_JOBS_FAILED: i32 = 2;
+
+/// Lines of failed job log written to fail summary
+const FAIL_SUMMARY_LOG_LINES: usize = 20;
+
+const EXIT_CODES: &str = "EXIT CODES:
+    0          All jobs succeeded or were skipped
+    1          Workflow failed
+    2          Workflow succeeded, but some jobs with continue_on_error failed
+    3          Workflow file is invalid, no job was run
+    4          Container image could not be pulled or is not available
+    5          Workflow, job or step timed out
+    128+N      Interrupted by signal N, e.g. 130 for SIGINT
+
+Successful workflow run with --exit-code-from exits with exit code of the named job instead.";
+
 #[derive(Parser, Debug)]
-#[clap(version, about, long_about = None)]
+#[clap(version, about, long_about = None, after_help = EXIT_CODES)]
 /// Prepare, run and collect iguana containers based on passed iguana workflow file
 struct Args {
     /// File with iguana workflow
-    #[clap(value_parser, forbid_empty_values = true)]
-    workflow: String,
+    /// May be a .tar, .tar.gz or .tgz bundle with control.yaml and files it uses,
+    /// or - to read the workflow from standard input
+    #[clap(
+        value_parser,
+        forbid_empty_values = true,
+        required_unless_present = "workflow-file"
+    )]
+    workflow: Option<String>,
+
+    /// Workflow file whose jobs and env are merged over the workflow, files given later win
+    /// May be specified multiple times or as comma separated list, the first one is
+    /// the workflow itself when no workflow argument is given
+    #[clap(
+        short = 'f',
+        long = "workflow",
+        value_parser,
+        value_name = "FILE",
+        value_delimiter = ','
+    )]
+    workflow_file: Vec<String>,
 
     /// Newroot mount directory
+    /// Mounted into every container as /sysroot, skipped when it does not exist
     #[clap(short, long, value_parser, default_value = "/sysroot")]
     newroot: String,
 
-    /// Do not run any action
+    /// Iguana state directory
+    /// Mounted into every container as /iguana, skipped when it does not exist
+    #[clap(long, value_parser, value_name = "DIR", default_value = "/iguana")]
+    iguana_dir: String,
+
+    /// Do not mount iguana state directory into containers
+    #[clap(long, conflicts_with = "iguana-dir")]
+    no_iguana_dir: bool,
+
+    /// Do not run any action, only print container runtime commands which would be run
     #[clap(long, takes_value = false)]
     dry_run: bool,
 
-    /// Log level
-    #[clap(long, default_value = "info", value_parser)]
-    log_level: String,
+    /// Log level, info by default, debug with --debug
+    #[clap(long, value_parser)]
+    log_level: Option<String>,
+
+    /// Log more, debug once given, trace when repeated, overrides RUST_LOG
+    #[clap(short, long, action = clap::ArgAction::Count, conflicts_with_all = &["log-level", "quiet"])]
+    verbose: u8,
+
+    /// Log less, only warnings once given, only errors when repeated, overrides RUST_LOG
+    #[clap(short, long, action = clap::ArgAction::Count, conflicts_with = "log-level")]
+    quiet: u8,
+
+    /// Colorize log output, auto colors only when writing to terminal
+    #[clap(long, value_enum, default_value = "auto")]
+    color: Color,
 
     /// Container debugging
-    /// If enabled, containers and their images will not be removed after run
+    /// If enabled, containers, their images and volumes will not be removed after run
+    /// unless --cleanup is given, log level defaults to debug
     #[clap(long, takes_value = false)]
     debug: bool,
 
-    /// Run privileged containers
+    /// Do not remove containers, their images and volumes after run, without changing log level
+    #[clap(long, takes_value = false, conflicts_with = "cleanup")]
+    no_cleanup: bool,
+
+    /// Remove containers, their images and volumes after run even with --debug
+    #[clap(long, takes_value = false)]
+    cleanup: bool,
+
+    /// Keep volumes created for jobs after the jobs finish
+    #[clap(long, takes_value = false)]
+    keep_volumes: bool,
+
+    /// Run containers privileged with host /dev mounted, the default
+    #[clap(long, takes_value = false, conflicts_with = "unprivileged")]
+    privileged: bool,
+
+    /// Run containers without privileged mode and host /dev
+    /// Workflows with containers which need privileged mode are rejected
     #[clap(short, long, takes_value = false)]
     unprivileged: bool,
+
+    /// Pass kernel command line parameters named PREFIX.<NAME> to all containers,
+    /// e.g. --env-from-cmdline=iguana passes iguana.tag=foo as TAG=foo
+    /// Applied before --env-from-json
+    #[clap(long, value_parser, value_name = "PREFIX", forbid_empty_values = true)]
+    env_from_cmdline: Option<String>,
+
+    /// File with flat JSON object of environmental variables passed to all containers
+    #[clap(long, value_parser, value_name = "PATH")]
+    env_from_json: Option<String>,
+
+    /// Flat JSON object of environmental variables passed to all containers
+    /// Applied after --env-from-json
+    #[clap(long, value_parser, value_name = "JSON")]
+    env_json: Option<String>,
+
+    /// Fail unless named job ran and finished successfully
+    /// May be specified multiple times
+    #[clap(long, value_parser, value_name = "NAME")]
+    require_job: Vec<String>,
+
+    /// Exit with exit code of the container of named job
+    #[clap(long, value_parser, value_name = "JOB")]
+    exit_code_from: Option<String>,
+
+    /// Stop starting jobs after first failed job, default unless workflow sets fail_fast
+    #[clap(long, takes_value = false, conflicts_with = "no-fail-fast")]
+    fail_fast: bool,
+
+    /// Keep running jobs independent of failed job, overrides workflow fail_fast
+    #[clap(long, takes_value = false)]
+    no_fail_fast: bool,
+
+    /// Run only named job, other jobs are neither pulled nor run
+    #[clap(long, value_parser, value_name = "JOB", conflicts_with_all = &["print-needs-closure", "debug-shell"])]
+    job: Option<String>,
+
+    /// With --job, run also all jobs the named job needs, in their usual order
+    #[clap(long, takes_value = false, requires = "job")]
+    with_deps: bool,
+
+    /// Run only jobs tagged with any of given tags and jobs they need
+    /// May be specified multiple times or as comma separated list, combines with --job
+    #[clap(long, value_parser, value_name = "TAG", value_delimiter = ',', conflicts_with_all = &["print-needs-closure", "debug-shell"])]
+    tags: Vec<String>,
+
+    /// Do not access network, use only images already present in local storage
+    /// Fails before running any job if some image is missing
+    #[clap(long, takes_value = false)]
+    offline: bool,
+
+    /// Seconds to wait for service to pass its healthcheck
+    /// Used when service does not specify its own timeout
+    #[clap(long, value_parser, default_value = "60", value_name = "SECONDS")]
+    health_timeout: u64,
+
+    /// Seconds between service healthchecks
+    /// Used when service does not specify its own interval
+    #[clap(long, value_parser, default_value = "2", value_name = "SECONDS")]
+    health_interval: u64,
+
+    /// Print tree of jobs, services and steps which would be run
+    #[clap(long, takes_value = false, requires = "dry-run")]
+    tree: bool,
+
+    /// Print jobs in the order they run with their image, needs and services
+    /// and exit without pulling or running anything
+    #[clap(long, takes_value = false, conflicts_with = "tree")]
+    list: bool,
+
+    /// Print jobs which must run before named job, in order, and exit without running
+    #[clap(long, value_parser, value_name = "JOB", conflicts_with_all = &["tree", "list"])]
+    print_needs_closure: Option<String>,
+
+    /// Set up container of named job as the real run would, but start interactive
+    /// /bin/sh in it instead of the job itself
+    #[clap(long, value_parser, value_name = "JOB", conflicts_with_all = &["tree", "list", "print-needs-closure"])]
+    debug_shell: Option<String>,
+
+    /// Resolve all images to digests in parallel, print them and exit without running
+    /// Fails when some image can not be resolved
+    #[clap(long, alias = "check", takes_value = false, conflicts_with_all = &["tree", "list", "print-needs-closure", "debug-shell"])]
+    validate_images_parallel: bool,
+
+    /// Validate the workflow, print its problems and exit without running it
+    /// Container runtime is not used, so images are checked only for valid references
+    #[clap(long, conflicts_with_all = &["tree", "list", "print-needs-closure", "debug-shell", "validate-images-parallel"])]
+    validate: bool,
+
+    /// Format of the workflow file
+    /// Auto detects it from .json, .yaml or .yml extension, or from the content
+    #[clap(long, value_enum, default_value = "auto")]
+    format: WorkflowFormat,
+
+    /// Container runtime used to run containers
+    /// Docker does not support image pull secrets and registries credentials
+    #[clap(long, value_enum, default_value = "podman")]
+    runtime: Runtime,
+
+    /// Path of container runtime binary, e.g. wrapper script, --runtime binary on PATH by default
+    #[clap(long, value_parser, value_name = "PATH")]
+    runtime_path: Option<PathBuf>,
+
+    /// Directory relative paths in the workflow, e.g. includes, env files and volume
+    /// sources, are resolved against, directory of the workflow file by default
+    #[clap(long, value_parser, value_name = "DIR")]
+    context: Option<PathBuf>,
+
+    /// Mount directory of the workflow file read-only into containers, so actions can
+    /// read scripts and templates shipped next to it
+    #[clap(long)]
+    mount_workflow_dir: bool,
+
+    /// Podman global option, e.g. --podman-global-arg=--root=/custom
+    /// May be specified multiple times, options are passed to every podman call,
+    /// or docker call with docker runtime, in given order, before the subcommand
+    #[clap(long, value_parser, allow_hyphen_values = true, value_name = "ARG")]
+    podman_global_arg: Vec<String>,
+
+    /// Label every container with KEY set to trimmed contents of file PATH
+    /// May be specified multiple times
+    #[clap(long, value_parser, value_name = "KEY=PATH")]
+    label_from_file: Vec<String>,
+
+    /// How to terminate job container exceeding its timeout
+    #[clap(long, value_enum, default_value = "stop")]
+    timeout_action: TimeoutAction,
+
+    /// Seconds stopped containers get to exit before they are killed, 0 kills them immediately
+    /// Overrides workflow stop_timeout, runtime default is used when neither is set
+    #[clap(long, value_parser, value_name = "SECONDS")]
+    stop_timeout: Option<u64>,
+
+    /// Seconds the whole workflow may run, started containers are stopped when exceeded
+    #[clap(long, value_parser, value_name = "SECONDS")]
+    timeout: Option<u64>,
+
+    /// When to pull container images not setting pull policy in workflow, --offline implies never
+    #[clap(long, value_enum, default_value = "always")]
+    pull_policy: PullPolicy,
+
+    /// How many times image pull is tried, pulls failing because image does not exist
+    /// or access is denied are not retried
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..), value_name = "N", default_value = "1")]
+    pull_attempts: u32,
+
+    /// Delay before retrying failed image pull, e.g. 2s, doubled before every next attempt
+    /// Actual delay is randomly between half and full delay
+    #[clap(long, value_parser = humantime::parse_duration, value_name = "DURATION", default_value = "2s")]
+    pull_retry_delay: Duration,
+
+    /// Pull again local images older than given age, e.g. 12h or 7d
+    /// Applies to missing pull policy
+    #[clap(long, value_parser = humantime::parse_duration, value_name = "DURATION")]
+    max_image_age: Option<Duration>,
+
+    /// Order of environmental variables passed to containers
+    /// Sorted order is deterministic, declared keeps order from workflow file
+    /// for images sensitive to variable ordering
+    #[clap(long, value_enum, default_value = "sorted")]
+    env_order: EnvOrder,
+
+    /// Run the whole workflow given number of times and report pass rate
+    /// Fails when any iteration fails
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..), default_value = "1", value_name = "N")]
+    repeat: u32,
+
+    /// Repeat the workflow until it fails, at most --repeat times when given
+    #[clap(long, takes_value = false)]
+    repeat_until_failure: bool,
+
+    /// Write failed jobs with their errors to file when the workflow fails
+    /// With --log-dir the last lines of logs of failed jobs are included
+    /// Existing file is removed when the workflow succeeds
+    #[clap(long, value_parser, value_name = "PATH")]
+    fail_summary_file: Option<String>,
+
+    /// Docker config JSON pull secret used to authenticate to registries
+    /// Both .dockerconfigjson and legacy .dockercfg formats are accepted
+    #[clap(long, value_parser, value_name = "PATH")]
+    image_pull_secret: Option<String>,
+
+    /// Directory to collect container logs into
+    /// Output of job containers and steps goes to <JOB>.log instead of the terminal,
+    /// logs of services are streamed to <JOB>.<SERVICE>.log while they run
+    #[clap(long, value_parser, value_name = "DIR")]
+    log_dir: Option<PathBuf>,
+
+    /// File with KEY=VALUE lines of environmental variables passed to all containers
+    /// Overrides variables from any other source, including container specific ones
+    /// and --env
+    #[clap(long, value_parser, value_name = "PATH")]
+    env_override_file: Option<String>,
+
+    /// Environmental variable passed to all containers, overrides variables from any
+    /// other source except --env-override-file
+    /// May be specified multiple times
+    #[clap(long = "env", value_parser = parse_env_pair, value_name = "KEY=VALUE")]
+    env_pairs: Vec<(String, String)>,
+
+    /// Directory to collect job artifacts into, each job into its own subdirectory
+    #[clap(long, value_parser, value_name = "DIR")]
+    result_dir: Option<PathBuf>,
+
+    /// Maximal number of independent jobs running in parallel, defaults to number of CPUs
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..), value_name = "N")]
+    max_parallel: Option<u32>,
+
+    /// Write JSON report with status, duration, error and steps of every job to PATH
+    #[clap(long, value_parser, value_name = "PATH")]
+    report: Option<PathBuf>,
+
+    /// Write status and outputs of every job to PATH, so failed run can be resumed
+    #[clap(long, value_parser, value_name = "PATH")]
+    state_file: Option<PathBuf>,
+
+    /// Resume run recorded in state file PATH, jobs which succeeded are not run again
+    /// State file is updated after the run unless --state-file is given
+    #[clap(long, value_parser, value_name = "PATH")]
+    resume: Option<PathBuf>,
+
+    /// Format of the summary of the run, json and kv are printed to standard output
+    #[clap(long, value_enum, default_value = "text")]
+    output_format: OutputFormat,
+
+    /// Memory limit of containers which do not set their own, e.g. 512m or 2g
+    #[clap(long, value_parser = parse_memory, value_name = "LIMIT")]
+    memory: Option<String>,
+
+    /// Number of CPUs containers which do not set their own can use, e.g. 2 or 0.5
+    #[clap(long, value_parser = parse_cpus, value_name = "CPUS")]
+    cpus: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Color {
+    Auto,
+    Always,
+    Never,
 }
 
-/// Tracking results of individual job runs
+/// Exit code of failed workflow by kind of the failure
+fn error_exit_code(error: &WorkflowError) -> i32 {
+    match error {
+        WorkflowError::ContainerRun(_) | WorkflowError::Io(_) => 1,
+        WorkflowError::Parse(_) | WorkflowError::Validation(_) => 3,
+        WorkflowError::ImagePull(_) | WorkflowError::ImageUnavailable(_) => 4,
+        WorkflowError::Timeout(_) => 5,
+    }
+}
+
+/// Reports workflow progress to the log and collects job failures
+#[derive(Default)]
+struct LogObserver {
+    failures: Mutex<Vec<(String, String)>>,
+}
+
+/// Progress of jobs is logged by the workflow itself
+impl RunObserver for LogObserver {
+    fn on_job_error(&self, name: &str, error: &str) {
+        self.failures
+            .lock()
+            .expect("Failures lock poisoned")
+            .push((name.to_owned(), error.to_owned()));
+    }
+
+    fn on_step_start(&self, job: &str, step: &str) {
+        info!("Job {job} running step {step}");
+    }
+
+    fn on_step_finish(&self, job: &str, step: &str, status: &StepStatus) {
+        match status {
+            StepStatus::Failed => warn!("Job {job} step {step} finished with status {status}"),
+            _ => debug!("Job {job} step {step} finished with status {status}"),
+        }
+    }
+}
+
+/// Write failed jobs and workflow error to fail summary file, remove the file on success
+///
+/// With log directory every failed job is followed by the last lines of its log, indented.
+fn write_fail_summary(
+    path: &str,
+    failures: &[(String, String)],
+    errors: &[String],
+    log_dir: Option<&Path>,
+) -> Result<(), String> {
+    let mut lines = Vec::new();
+    for (name, e) in failures.iter() {
+        lines.push(format!("{name}: {e}"));
+        // Job may fail before its container logged anything
+        if let Some(Ok(log)) = log_dir.map(|d| fs::read(d.join(format!("{name}.log")))) {
+            let log = String::from_utf8_lossy(&log);
+            let tail: Vec<_> = log.lines().rev().take(FAIL_SUMMARY_LOG_LINES).collect();
+            lines.extend(tail.iter().rev().map(|l| format!("    {l}")));
+        }
+    }
+    // Workflow error is usually the error of the last failed job
+    for e in errors.iter() {
+        if !failures.iter().any(|(_, f)| f == e) {
+            lines.push(format!("workflow: {e}"));
+        }
+    }
+
+    if lines.is_empty() {
+        return match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                Err(format!("Unable to remove fail summary file {path}: {e}"))
+            }
+            _ => Ok(()),
+        };
+    }
+    lines.push(String::new());
+    match fs::write(path, secrets::mask(&lines.join("\n"))) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("Unable to write fail summary file {path}: {e}")),
+    }
+}
+
+/// Create output directory and return its absolute path
+///
+/// Absolute path keeps the directory valid when running from bundle directory
+fn output_dir(dir: &Path) -> Result<PathBuf, String> {
+    match fs::create_dir_all(dir).and_then(|_| fs::canonicalize(dir)) {
+        Ok(d) => Ok(d),
+        Err(e) => Err(format!("{}: {e}", dir.display())),
+    }
+}
+
+/// Parse variable given on command line as `KEY=VALUE`, value may be empty
+fn parse_env_pair(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((k, v)) => match env_key_problem(k) {
+            Some(problem) => Err(format!("'{value}' has {problem}")),
+            None => Ok((k.to_owned(), v.to_owned())),
+        },
+        None => Err(format!("'{value}' is not in KEY=VALUE format")),
+    }
+}
+
+/// Read label value from file given as `KEY=PATH`
+fn read_label_file(spec: &str) -> Result<(String, String), String> {
+    let (key, path) = match spec.split_once('=') {
+        Some((k, p)) if !k.is_empty() && !p.is_empty() => (k, p),
+        _ => return Err(format!("Invalid label file '{spec}', expected KEY=PATH")),
+    };
+    match fs::read_to_string(path) {
+        Ok(value) => Ok((key.to_owned(), value.trim().to_owned())),
+        Err(e) => Err(format!("Unable to read file {path} for label {key}: {e}")),
+    }
+}
+
+/// Parse flat JSON object into environment map
+///
+/// String, number and boolean values are used as they are, null maps to empty string.
+/// Nested objects and arrays are rejected.
+fn parse_env_json(data: &str) -> Result<LinkedHashMap<String, String>, String> {
+    let value: Value = match serde_json::from_str(data) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Unable to parse environment JSON: {e}")),
+    };
+
+    let object = match value {
+        Value::Object(o) => o,
+        _ => return Err("Environment JSON must be an object".to_owned()),
+    };
+
+    let mut env = LinkedHashMap::new();
+    for (k, v) in object {
+        let v = match v {
+            Value::String(s) => s,
+            Value::Number(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Null => String::new(),
+            Value::Array(_) | Value::Object(_) => {
+                return Err(format!(
+                    "Environment variable '{k}' has nested value, only flat JSON objects are supported"
+                ));
+            }
+        };
+        env.insert(k, v);
+    }
+    Ok(env)
+}
 
 fn main() {
     let args = Args::parse();
-    env_logger::Builder::from_env(Env::default().default_filter_or(args.log_level)).init();
+    let write_style = match args.color {
+        Color::Auto => WriteStyle::Auto,
+        Color::Always => WriteStyle::Always,
+        Color::Never => WriteStyle::Never,
+    };
+    // Summary is logged, so it is colored when log output is
+    let color = match args.color {
+        Color::Auto => io::stderr().is_terminal(),
+        Color::Always => true,
+        Color::Never => false,
+    };
+    let log_level = match &args.log_level {
+        Some(level) => level,
+        None if args.debug => "debug",
+        None => "info",
+    };
+    // Verbosity flags replace RUST_LOG filter, other levels only default it
+    let verbosity = match (args.verbose, args.quiet) {
+        (0, 0) => None,
+        (1, _) => Some(LevelFilter::Debug),
+        (_, 0) => Some(LevelFilter::Trace),
+        (0, 1) => Some(LevelFilter::Warn),
+        _ => Some(LevelFilter::Error),
+    };
+    let mut builder = match verbosity {
+        Some(level) => {
+            let mut builder = env_logger::Builder::new();
+            builder.filter_level(level);
+            builder
+        }
+        None => env_logger::Builder::from_env(Env::default().default_filter_or(log_level)),
+    };
+    let logger = builder.write_style(write_style).build();
+    log::set_max_level(logger.filter());
+    log::set_boxed_logger(Box::new(MaskingLogger::new(logger)))
+        .expect("logger is initialized only once");
 
-    let workflow_file = args.workflow;
+    // Workflow argument is the base, otherwise the first workflow option is
+    let mut layers = args.workflow.iter().chain(args.workflow_file.iter());
+    let workflow = layers.next().expect("workflow is required").to_owned();
+    let mut overlays = Vec::new();
+    for layer in layers {
+        match fs::canonicalize(layer) {
+            Ok(path) if path.is_file() => overlays.push(path),
+            _ => {
+                error!("No such file: {layer}");
+                exit(1);
+            }
+        }
+    }
+
+    let from_stdin = workflow == STDIN_WORKFLOW;
+    let mut workflow_file = PathBuf::from(&workflow);
     // Is workflow URL or file
-    info!("Using workflow file {}", workflow_file);
-    if !Path::is_file(Path::new(&workflow_file)) {
-        error!("No such file: {}", workflow_file);
+    if from_stdin {
+        info!("Reading workflow from standard input");
+    } else {
+        info!("Using workflow file {workflow}");
+    }
+    if !from_stdin && !Path::is_file(&workflow_file) {
+        error!("No such file: {workflow}");
         exit(1);
     }
 
-    let workflow_data = fs::read_to_string(workflow_file).expect("Unable to open workflow file");
+    // Bundles are extracted and run from within the extracted directory
+    let mut bundle_dir = None;
+    if !from_stdin && bundle::is_bundle(&workflow) {
+        match bundle::extract_bundle(&workflow) {
+            Ok(dir) => {
+                workflow_file = dir.join(bundle::BUNDLE_CONTROL_FILE);
+                bundle_dir = Some(dir);
+            }
+            Err(e) => {
+                error!("{e}");
+                exit(1);
+            }
+        }
+    }
+
+    let format = match args.format {
+        WorkflowFormat::Auto => WorkflowFormat::from_path(&workflow_file),
+        f => f,
+    };
+    let workflow_data = if from_stdin {
+        let mut data = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut data) {
+            error!("Unable to read workflow from standard input: {e}");
+            exit(1);
+        }
+        data
+    } else {
+        fs::read_to_string(&workflow_file).expect("Unable to open workflow file")
+    };
+
+    let mut env = LinkedHashMap::new();
+    if let Some(prefix) = &args.env_from_cmdline {
+        env.extend(cmdline::cmdline_env(prefix));
+    }
+    if let Some(path) = &args.env_from_json {
+        let data = match fs::read_to_string(path) {
+            Ok(d) => d,
+            Err(e) => {
+                error!("Unable to read environment file {path}: {e}");
+                exit(1);
+            }
+        };
+        match parse_env_json(&data).and_then(|e| check_env_names(path, &e).map(|_| e)) {
+            Ok(e) => env.extend(e),
+            Err(e) => {
+                error!("{path}: {e}");
+                exit(1);
+            }
+        }
+    }
+    if let Some(data) = &args.env_json {
+        match parse_env_json(data).and_then(|e| check_env_names("--env-json", &e).map(|_| e)) {
+            Ok(e) => env.extend(e),
+            Err(e) => {
+                error!("{e}");
+                exit(1);
+            }
+        }
+    }
+
+    // Override file is the authoritative layer, above --env
+    let mut env_override: LinkedHashMap<_, _> = args.env_pairs.iter().cloned().collect();
+    if let Some(path) = &args.env_override_file {
+        let parsed = match fs::read_to_string(path) {
+            Ok(d) => parse_env_file(&d).and_then(|e| check_env_names(path, &e).map(|_| e)),
+            Err(e) => Err(format!("Unable to read environment file: {e}")),
+        };
+        match parsed {
+            Ok(e) => env_override.extend(e),
+            Err(e) => {
+                error!("{path}: {e}");
+                exit(1);
+            }
+        }
+    }
+
+    let mut labels = Vec::new();
+    for spec in args.label_from_file.iter() {
+        match read_label_file(spec) {
+            Ok(label) => labels.push(label),
+            Err(e) => {
+                error!("{e}");
+                exit(1);
+            }
+        }
+    }
+
+    let log_dir = match args.log_dir.as_deref().map(output_dir).transpose() {
+        Ok(d) => d,
+        Err(e) => {
+            error!("Unable to create log directory: {e}");
+            exit(1);
+        }
+    };
+
+    let result_dir = match args.result_dir.as_deref().map(output_dir).transpose() {
+        Ok(d) => d,
+        Err(e) => {
+            error!("Unable to create result directory: {e}");
+            exit(1);
+        }
+    };
+
+    // Keep temporary authfile until the workflow finishes
+    let authfile = match &args.image_pull_secret {
+        Some(_) if args.runtime == Runtime::Docker => {
+            error!("Image pull secret is not supported with docker runtime, use docker login");
+            exit(1);
+        }
+        Some(path) => match auth::prepare_authfile(path) {
+            Ok(a) => Some(a),
+            Err(e) => {
+                error!("{e}");
+                exit(1);
+            }
+        },
+        None => None,
+    };
+
+    if let Some(path) = &args.runtime_path {
+        if !path.is_file() {
+            error!("Container runtime {} does not exist", path.display());
+            exit(1);
+        }
+    }
+
+    // Workflow read from standard input is relative to the current directory
+    let context = match &args.context {
+        Some(dir) => match fs::canonicalize(dir) {
+            Ok(p) if p.is_dir() => Some(p),
+            _ => {
+                error!("Context directory {} does not exist", dir.display());
+                exit(1);
+            }
+        },
+        None if from_stdin => None,
+        None => fs::canonicalize(&workflow_file)
+            .ok()
+            .and_then(|f| f.parent().map(Path::to_owned)),
+    };
+
+    let workflow_dir = match from_stdin {
+        _ if !args.mount_workflow_dir => None,
+        true => {
+            debug!("Workflow read from standard input has no directory to mount");
+            None
+        }
+        false => fs::canonicalize(&workflow_file)
+            .ok()
+            .and_then(|f| f.parent().map(Path::to_owned)),
+    };
+
+    let newroot = match fs::canonicalize(&args.newroot) {
+        Ok(p) if p.is_dir() => Some(p),
+        _ => {
+            warn!(
+                "Newroot directory {} does not exist, it is not mounted into containers",
+                args.newroot
+            );
+            None
+        }
+    };
+
+    let iguana_dir = match fs::canonicalize(&args.iguana_dir) {
+        _ if args.no_iguana_dir => None,
+        Ok(p) if p.is_dir() => Some(p),
+        _ => {
+            warn!(
+                "Iguana directory {} does not exist, it is not mounted into containers",
+                args.iguana_dir
+            );
+            None
+        }
+    };
 
     let opts = WorkflowOptions {
-        debug: args.debug,
+        // Explicit cleanup flags take precedence over --debug
+        cleanup: args.cleanup || !(args.no_cleanup || args.debug),
         dry_run: args.dry_run,
         privileged: !args.unprivileged,
+        env,
+        env_override,
+        required_jobs: args.require_job,
+        offline: args.offline,
+        health_timeout: args.health_timeout,
+        health_interval: args.health_interval,
+        tree: args.tree,
+        list: args.list,
+        needs_closure: args.print_needs_closure,
+        job: args.job,
+        with_deps: args.with_deps,
+        tags: args.tags,
+        fail_fast: match (args.fail_fast, args.no_fail_fast) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        },
+        debug_shell: args.debug_shell,
+        validate_images: args.validate_images_parallel,
+        validate: args.validate,
+        format,
+        runtime: args.runtime,
+        runtime_path: args.runtime_path,
+        memory: args.memory,
+        cpus: args.cpus,
+        report: args.report,
+        state_file: args.state_file,
+        resume: args.resume,
+        output_format: args.output_format,
+        color,
+        workflow_file: if from_stdin {
+            None
+        } else {
+            fs::canonicalize(&workflow_file).ok()
+        },
+        overlays,
+        context,
+        workflow_dir,
+        pull_timeout: None,
+        pull_attempts: args.pull_attempts,
+        pull_retry_delay: args.pull_retry_delay,
+        run_id: String::new(),
+        podman_global_args: args.podman_global_arg,
+        labels,
+        timeout_action: args.timeout_action,
+        stop_timeout: args.stop_timeout,
+        timeout: args.timeout.map(Duration::from_secs),
+        pull_policy: args.pull_policy,
+        max_image_age: args.max_image_age,
+        env_order: args.env_order,
+        max_parallel: match args.max_parallel {
+            Some(n) => n as usize,
+            None => thread::available_parallelism().map_or(1, |n| n.get()),
+        },
+        authfile: authfile.as_ref().map(|a| a.path.clone()),
+        log_dir,
+        result_dir,
+        newroot,
+        iguana_dir,
+        keep_volumes: args.keep_volumes,
     };
 
-    if let Err(e) = do_workflow(workflow_data, &opts) {
-        error!("{}", e);
+    if let Some(dir) = &bundle_dir {
+        debug!("Running from bundle directory {}", dir.display());
+        if let Err(e) = env::set_current_dir(dir) {
+            error!("Unable to enter bundle directory {}: {e}", dir.display());
+            exit(1);
+        }
+    }
+
+    if let Err(e) = interrupt::install(opts.clone()) {
+        error!("{e}");
         exit(1);
-    } else {
-        info!("Iguana workflow finished successfully");
-        exit(0);
     }
+
+    let repeating = args.repeat > 1 || args.repeat_until_failure;
+    let mut iteration = 0;
+    let mut passed = 0;
+    let observer = LogObserver::default();
+    let mut errors = Vec::new();
+    let result = loop {
+        iteration += 1;
+        if repeating {
+            info!("Starting workflow iteration {iteration}");
+        }
+        let result = do_workflow(workflow_data.clone(), &opts, &observer);
+        match &result {
+            Ok(_) => {
+                passed += 1;
+                if repeating {
+                    info!("Workflow iteration {iteration} succeeded");
+                }
+            }
+            Err(e) => {
+                if repeating {
+                    error!("Workflow iteration {iteration} failed: {e}");
+                }
+                errors.push(e.to_string());
+            }
+        }
+        let failed = result.is_err();
+        // --repeat-until-failure without explicit --repeat runs until the first failure
+        let limit_reached = if args.repeat_until_failure && args.repeat == 1 {
+            false
+        } else {
+            iteration >= args.repeat
+        };
+        if limit_reached || (failed && args.repeat_until_failure) {
+            break result;
+        }
+    };
+
+    if repeating {
+        info!(
+            "Workflow passed {passed} of {iteration} iterations ({:.1}%)",
+            f64::from(passed) * 100.0 / f64::from(iteration)
+        );
+    }
+
+    drop(authfile);
+    if let Some(dir) = &bundle_dir {
+        if let Err(e) = fs::remove_dir_all(dir) {
+            warn!("Unable to remove bundle directory {}: {e}", dir.display());
+        }
+    }
+
+    if let Some(path) = &args.fail_summary_file {
+        let failures = observer.failures.lock().expect("Failures lock poisoned");
+        if let Err(e) = write_fail_summary(path, &failures, &errors, opts.log_dir.as_deref()) {
+            error!("{e}");
+        }
+    }
+
+    let jobs_status = match result {
+        Ok(_) if passed < iteration => exit(1),
+        Ok(s) => s,
+        Err(e) => {
+            if !repeating {
+                error!("{}", e);
+            }
+            exit(error_exit_code(&e));
+        }
+    };
+    info!("Iguana workflow finished successfully");
+    let failed = jobs_status
+        .values()
+        .filter(|r| r.status == JobStatus::Failed)
+        .count();
+    let skipped = jobs_status
+        .values()
+        .filter(|r| r.status == JobStatus::Skipped)
+        .count();
+    if skipped > 0 {
+        info!("{skipped} of {} jobs were skipped", jobs_status.len());
+    }
+
+    if let Some(job) = &args.exit_code_from {
+        match jobs_status.get(job) {
+            Some(result) => match result.exit_code {
+                Some(code) => exit(code),
+                None if result.status == JobStatus::Success => exit(0),
+                None => {
+                    warn!("Job {job} did not provide exit code");
+                    exit(1);
+                }
+            },
+            None => {
+                error!("Job {job} requested by --exit-code-from is not part of the workflow");
+                exit(1);
+            }
+        }
+    }
+    if failed > 0 {
+        warn!(
+            "{failed} of {} jobs failed and were ignored because of continue_on_error",
+            jobs_status.len()
+        );
+        exit(EXIT_JOBS_FAILED);
+    }
+    exit(0);
 }
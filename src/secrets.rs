@@ -0,0 +1,68 @@
+/// Masking of secret values in log output
+use log::{Log, Metadata, Record};
+use std::sync::RwLock;
+
+const MASK: &str = "***";
+
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Register values which must not appear in log output
+pub fn register<'a>(values: impl IntoIterator<Item = &'a String>) {
+    let mut secrets = SECRETS.write().unwrap();
+    for value in values {
+        if !value.is_empty() && !secrets.contains(value) {
+            secrets.push(value.clone());
+        }
+    }
+    // Longer secrets first so secret containing another one is masked whole
+    secrets.sort_unstable_by_key(|s| std::cmp::Reverse(s.len()));
+}
+
+/// Replace every registered secret value in text
+pub fn mask(text: &str) -> String {
+    let secrets = SECRETS.read().unwrap();
+    let mut text = text.to_owned();
+    for secret in secrets.iter() {
+        if text.contains(secret.as_str()) {
+            text = text.replace(secret.as_str(), MASK);
+        }
+    }
+    text
+}
+
+/// Logger masking secrets in messages before passing them to the wrapped logger
+pub struct MaskingLogger<L: Log> {
+    inner: L,
+}
+
+impl<L: Log> MaskingLogger<L> {
+    pub fn new(inner: L) -> Self {
+        MaskingLogger { inner }
+    }
+}
+
+impl<L: Log> Log for MaskingLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+        let message = mask(&record.args().to_string());
+        self.inner.log(
+            &Record::builder()
+                .args(format_args!("{message}"))
+                .metadata(record.metadata().clone())
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build(),
+        );
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
@@ -1,39 +1,357 @@
 use linked_hash_map::LinkedHashMap;
-use log::info;
+use log::{debug, error, info, warn};
 /// Implementation of Iguana workflow parsing
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::net::IpAddr;
 use std::option::Option;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use crate::auth::{self, Authfile, Credentials};
+use crate::engines::podman::{self, Podman};
+use crate::interrupt;
+use crate::secrets;
+
+mod condition;
+mod duplicates;
+mod error;
+mod include;
+mod interpolate;
 mod job;
+mod matrix;
+mod reference;
+mod report;
+mod state;
+mod summary;
+#[cfg(test)]
+pub(crate) mod testing;
+mod tree;
+
+use condition::Condition;
+pub use error::WorkflowError;
+pub use job::{env_name, JobResult, JobStatus, StepStatus};
+
+/// Oldest control file schema version supported by this build
+const MIN_CONTROL_VERSION: u32 = 1;
+/// Newest control file schema version supported by this build
+const MAX_CONTROL_VERSION: u32 = 1;
+/// Prefix of network joining network namespace of service of the job
+const SERVICE_NETWORK_PREFIX: &str = "service:";
+
+/// Name of the job run after all other jobs regardless of their result
+pub const FINALLY_JOB: &str = "finally";
+
+/// Network of containers which do not specify one
+pub const HOST_NETWORK: &str = "host";
 
 /// Container
-#[derive(Deserialize)]
+#[derive(Clone, Default, Deserialize)]
 pub struct Container {
+    #[serde(default)]
     pub image: String,
-    pub env: Option<HashMap<String, String>>,
+    /// Use filesystem of main container of named job as image
+    pub from_job: Option<String>,
+    /// When to pull the image, defaults to workflow pull policy
+    pub pull: Option<PullPolicy>,
+    pub env: Option<LinkedHashMap<String, String>>,
+    /// File with `KEY=VALUE` lines merged under env
+    pub env_file: Option<String>,
     pub volumes: Option<Vec<String>>,
+    pub tmpfs: Option<Vec<String>>,
+    pub devices: Option<Vec<String>>,
+    /// GPUs passed to the container, `all`, their count or `device=<ids>`
+    pub gpus: Option<String>,
+    /// Network of the container, host network when not set
+    pub network: Option<String>,
+    /// Ports published to the host, requires network other than host
+    pub ports: Option<Vec<String>>,
+    /// Hostname of the container, services default to their name unless on host network
+    pub hostname: Option<String>,
+    /// Static host entries of the container, hostname to IP address
+    pub extra_hosts: Option<LinkedHashMap<String, String>>,
+    /// Memory limit, e.g. `512m`, defaults to `--memory` option
+    pub memory: Option<String>,
+    /// Number of CPUs the container can use, defaults to `--cpus` option
+    pub cpus: Option<f64>,
+    /// Override image entrypoint, empty string clears it
+    pub entrypoint: Option<String>,
+    /// Override image command, arguments passed to the entrypoint
+    pub command: Option<Vec<String>>,
+    /// User running the container, `uid`, `uid:gid` or user name
+    pub user: Option<String>,
+    /// Labels of the container, merged over workflow labels
+    pub labels: Option<LinkedHashMap<String, String>>,
+    /// Restart policy of service container
+    pub restart: Option<RestartPolicy>,
+    pub healthcheck: Option<Healthcheck>,
+    /// Working directory of the container, default one of steps of the job
+    #[serde(alias = "working-directory")]
+    pub working_directory: Option<String>,
+    /// Run main container of the job in background, job finishes once it starts
+    #[serde(default)]
+    pub detach: bool,
+    /// Container requires privileged mode with access to host devices
+    #[serde(default)]
+    pub needs_privileged: bool,
+    /// Job runs even when the service fails to start
+    #[serde(default)]
+    pub optional: bool,
 }
 
-/// Step
+impl Container {
+    /// Container is restarted by the runtime when it exits
+    pub fn restarts(&self) -> bool {
+        self.restart.is_some_and(|r| r != RestartPolicy::No)
+    }
+
+    /// Container can not work without privileged mode
+    fn requires_privileged(&self) -> bool {
+        let device_volume = self.volumes.iter().flatten().any(|v| {
+            let src = v.split(':').next().unwrap_or_default();
+            src == "/dev" || src.starts_with("/dev/")
+        });
+        self.needs_privileged || device_volume
+    }
+
+    /// Fill settings the container does not set from workflow defaults, maps are merged
+    /// with container values winning
+    fn apply_defaults(&mut self, defaults: &Container) {
+        fn merge(
+            value: &mut Option<LinkedHashMap<String, String>>,
+            defaults: &Option<LinkedHashMap<String, String>>,
+        ) {
+            if let Some(defaults) = defaults {
+                let mut merged = defaults.clone();
+                merged.extend(value.take().unwrap_or_default());
+                *value = Some(merged);
+            }
+        }
+        fn fill<T: Clone>(value: &mut Option<T>, defaults: &Option<T>) {
+            if value.is_none() {
+                value.clone_from(defaults);
+            }
+        }
+        fill(&mut self.pull, &defaults.pull);
+        merge(&mut self.env, &defaults.env);
+        fill(&mut self.env_file, &defaults.env_file);
+        fill(&mut self.volumes, &defaults.volumes);
+        fill(&mut self.tmpfs, &defaults.tmpfs);
+        fill(&mut self.devices, &defaults.devices);
+        fill(&mut self.gpus, &defaults.gpus);
+        fill(&mut self.network, &defaults.network);
+        fill(&mut self.ports, &defaults.ports);
+        fill(&mut self.memory, &defaults.memory);
+        fill(&mut self.cpus, &defaults.cpus);
+        fill(&mut self.entrypoint, &defaults.entrypoint);
+        fill(&mut self.user, &defaults.user);
+        merge(&mut self.labels, &defaults.labels);
+        merge(&mut self.extra_hosts, &defaults.extra_hosts);
+        fill(&mut self.working_directory, &defaults.working_directory);
+        self.needs_privileged |= defaults.needs_privileged;
+    }
+
+    /// Named volumes of the container, host directories and anonymous volumes are skipped
+    pub fn named_volumes(&self) -> impl Iterator<Item = &str> {
+        self.volumes
+            .iter()
+            .flatten()
+            .filter_map(|v| match v.split_once(':') {
+                Some((src, _)) if !src.starts_with('/') && !src.starts_with('.') => Some(src),
+                _ => None,
+            })
+    }
+}
+
+/// Healthcheck of service container
+#[derive(Clone, Default, Deserialize)]
+pub struct Healthcheck {
+    /// Shell command run inside the container, healthy when it exits with 0
+    pub cmd: String,
+    /// Seconds to wait for container to become healthy
+    pub timeout: Option<u64>,
+    /// Seconds between checks
+    pub interval: Option<u64>,
+    /// Failed checks after which container is unhealthy even before timeout
+    pub retries: Option<u32>,
+}
+
+/// Credentials of container registry, either username and password or authfile
 #[derive(Deserialize)]
+pub struct Registry {
+    username: Option<String>,
+    password: Option<String>,
+    /// Pull secret or authfile with credentials of the registry
+    authfile: Option<String>,
+}
+
+/// Step
+#[derive(Clone, Deserialize)]
 pub struct Step {
     name: Option<String>,
-    run: String,
+    /// Identifier of the step for references to its outputs
+    id: Option<String>,
+    /// Shell command run in job container
+    run: Option<String>,
+    /// Shell running the command, `/bin/sh` by default
+    shell: Option<String>,
+    /// Written verbatim to standard input of the command
+    input: Option<String>,
+    /// Image or local action directory run as the step implementation instead of `run` command
     uses: Option<String>,
-    with: Option<String>,
-    env: Option<HashMap<String, String>>,
+    /// Inputs of `uses` image, passed as `INPUT_<KEY>` environmental variables
+    with: Option<LinkedHashMap<String, String>>,
+    env: Option<LinkedHashMap<String, String>>,
+    /// Directory in which the step runs, it must exist
+    #[serde(alias = "working-directory")]
+    working_directory: Option<String>,
+    /// Run the step only when condition holds
+    #[serde(rename = "if")]
+    condition: Option<Condition>,
+    /// Failure of the step does not fail the job
+    #[serde(default)]
+    continue_on_error: bool,
+}
+
+impl Step {
+    /// Step name for logs, steps without name are numbered from 1
+    fn display_name(&self, index: usize) -> String {
+        match &self.name {
+            Some(name) => name.to_owned(),
+            None => format!("#{}", index + 1),
+        }
+    }
+
+    /// Directory of local action the step uses, relative to context directory or absolute
+    fn local_action(&self) -> Option<&str> {
+        self.uses
+            .as_deref()
+            .filter(|u| u.starts_with('.') || u.starts_with('/'))
+    }
+}
+/// Jobs which must run before the job
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Needs {
+    /// All listed jobs must succeed
+    All(Vec<String>),
+    /// At least one of listed jobs must succeed
+    AnyOf { any_of: Vec<String> },
 }
+
+impl Needs {
+    /// Names of all needed jobs
+    pub fn jobs(&self) -> &[String] {
+        match self {
+            Needs::All(jobs) => jobs,
+            Needs::AnyOf { any_of } => any_of,
+        }
+    }
+}
+
 /// Job
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub struct Job {
     container: Container,
     services: Option<HashMap<String, Container>>,
-    needs: Option<Vec<String>>,
+    needs: Option<Needs>,
+    /// Steps run before all other steps
+    pre: Option<Vec<Step>>,
     steps: Option<Vec<Step>>,
+    /// Steps run after all other steps, even when they failed or timed out
+    post: Option<Vec<Step>>,
     #[serde(default)]
     continue_on_error: bool,
+    /// Seconds after which job container is terminated
+    timeout: Option<u64>,
+    /// Paths inside main container copied out after the job with `--result-dir`
+    artifacts: Option<Vec<String>>,
+    /// Run failed job again
+    retry: Option<Retry>,
+    /// Environment of all job containers never shown in logs
+    secrets: Option<LinkedHashMap<String, String>>,
+    /// Run the job only when condition holds
+    #[serde(rename = "if")]
+    condition: Option<Condition>,
+    /// Run job once for every combination of listed values
+    matrix: Option<LinkedHashMap<String, Vec<String>>>,
+    /// File with `KEY=VALUE` lines passed to all job containers
+    env_file: Option<String>,
+    /// Tags selecting the job with `--tags`
+    tags: Option<Vec<String>>,
+    /// Environment loaded from env_file
+    #[serde(skip)]
+    file_env: LinkedHashMap<String, String>,
+}
+
+/// Retry of failed job
+#[derive(Clone, Deserialize)]
+pub struct Retry {
+    /// Number of times the job is run at most, including the first run
+    pub attempts: u32,
+    /// Seconds to wait before next attempt
+    #[serde(default)]
+    pub delay: u64,
+}
+
+impl Job {
+    /// Names of jobs this job needs, regardless of needs combinator
+    fn needed_jobs(&self) -> &[String] {
+        self.needs.as_ref().map(|n| n.jobs()).unwrap_or_default()
+    }
+
+    /// Jobs which must finish before this job starts, needed jobs and jobs
+    /// whose committed container is used as base of job containers
+    fn run_after(&self) -> impl Iterator<Item = &str> {
+        self.needed_jobs()
+            .iter()
+            .map(|n| n.as_str())
+            .chain(self.containers().filter_map(|(_, c)| c.from_job.as_deref()))
+    }
+
+    /// Pre steps, steps and post steps in the order they run
+    fn all_steps(&self) -> impl Iterator<Item = &Step> {
+        self.pre
+            .iter()
+            .chain(self.steps.iter())
+            .chain(self.post.iter())
+            .flatten()
+    }
+
+    fn all_steps_mut(&mut self) -> impl Iterator<Item = &mut Step> {
+        self.pre
+            .iter_mut()
+            .chain(self.steps.iter_mut())
+            .chain(self.post.iter_mut())
+            .flatten()
+    }
+
+    /// Whether job runs any steps in its main container
+    fn has_steps(&self) -> bool {
+        self.all_steps().next().is_some()
+    }
+
+    /// Main container followed by service containers, with their display names
+    fn containers(&self) -> impl Iterator<Item = (&str, &Container)> {
+        std::iter::once((job::MAIN_CONTAINER, &self.container)).chain(
+            self.services
+                .iter()
+                .flatten()
+                .map(|(s_name, s)| (s_name.as_str(), s)),
+        )
+    }
+}
+
+/// Schema version declared by control file, parsed before the rest of the workflow
+#[derive(Deserialize)]
+struct ControlVersion {
+    version: Option<u32>,
 }
 
 /// Workflow
@@ -41,39 +359,1676 @@ pub struct Job {
 pub struct Workflow {
     name: Option<String>,
     description: Option<String>,
+    /// Workflow files whose jobs and env are merged under this workflow
+    include: Option<Vec<String>>,
+    #[serde(default)]
     jobs: LinkedHashMap<String, Job>,
-    env: Option<HashMap<String, String>>,
+    /// Job run after all other jobs, whether the workflow succeeded or not
+    finally: Option<Job>,
+    env: Option<LinkedHashMap<String, String>>,
+    /// File with `KEY=VALUE` lines merged under env
+    env_file: Option<String>,
+    /// Pull policy of containers which do not set their own
+    pull: Option<PullPolicy>,
+    /// User of containers which do not set their own
+    user: Option<String>,
+    /// Network of containers which do not set their own
+    network: Option<String>,
+    /// Labels of all containers
+    labels: Option<LinkedHashMap<String, String>>,
+    /// Settings of all containers which do not set their own
+    defaults: Option<Container>,
+    /// Seconds single image pull may take
+    pull_timeout: Option<u64>,
+    /// Stop starting jobs after first failure, true by default
+    fail_fast: Option<bool>,
+    /// Seconds stopped containers get to exit before they are killed
+    stop_timeout: Option<u64>,
+    /// Environment never shown in logs
+    secrets: Option<LinkedHashMap<String, String>>,
+    /// Credentials used for pulling images from listed registries
+    registries: Option<LinkedHashMap<String, Registry>>,
+    order: Option<Vec<String>>,
 }
 
+#[derive(Clone)]
 pub struct WorkflowOptions {
     pub dry_run: bool,
-    pub debug: bool,
+    /// Remove containers, images and volumes once they are no longer needed
+    pub cleanup: bool,
     pub privileged: bool,
+    /// Environment passed on command line, overrides workflow environment
+    pub env: LinkedHashMap<String, String>,
+    /// Environment from --env and override file, overrides any other environment
+    pub env_override: LinkedHashMap<String, String>,
+    /// Jobs which must finish successfully for workflow to succeed
+    pub required_jobs: Vec<String>,
+    /// Never access network, use only locally available images
+    pub offline: bool,
+    /// Default seconds to wait for service to become healthy
+    pub health_timeout: u64,
+    /// Default seconds between service health checks
+    pub health_interval: u64,
+    /// Print tree of the normalized workflow instead of running it
+    pub tree: bool,
+    /// Print table of jobs in the order they run instead of running the workflow
+    pub list: bool,
+    /// Print jobs which must run before named job instead of running the workflow
+    pub needs_closure: Option<String>,
+    /// Run only named job
+    pub job: Option<String>,
+    /// Run also jobs needed by the job selected to run
+    pub with_deps: bool,
+    /// Run only jobs with any of the tags and jobs they need
+    pub tags: Vec<String>,
+    /// Stop starting jobs after failure, workflow setting is used when not given
+    pub fail_fast: Option<bool>,
+    /// Start interactive shell in container of named job instead of running the workflow
+    pub debug_shell: Option<String>,
+    /// Resolve and print digests of all images instead of running the workflow
+    pub validate_images: bool,
+    /// Only load and validate the workflow
+    pub validate: bool,
+    /// Format of the workflow file
+    pub format: WorkflowFormat,
+    /// Container runtime binary
+    pub runtime: Runtime,
+    /// Path of container runtime binary overriding the one found in PATH
+    pub runtime_path: Option<PathBuf>,
+    /// Podman global options, passed before podman subcommand
+    pub podman_global_args: Vec<String>,
+    /// Labels added to every container
+    pub labels: Vec<(String, String)>,
+    /// How to terminate job container after its timeout
+    pub timeout_action: TimeoutAction,
+    /// Seconds stopped containers get to exit before they are killed
+    pub stop_timeout: Option<u64>,
+    /// Limit of the whole workflow run, started containers are stopped when exceeded
+    pub timeout: Option<Duration>,
+    /// When to pull container images
+    pub pull_policy: PullPolicy,
+    /// Pull again local images older than this even with missing pull policy
+    pub max_image_age: Option<Duration>,
+    /// Order of environmental variables passed to containers
+    pub env_order: EnvOrder,
+    /// Maximal number of jobs running at once
+    pub max_parallel: usize,
+    /// Registry authentication file used for pulling images
+    pub authfile: Option<PathBuf>,
+    /// Directory where container logs are collected
+    pub log_dir: Option<PathBuf>,
+    /// Directory where job artifacts are collected
+    pub result_dir: Option<PathBuf>,
+    /// Host directory of the new root mounted into containers
+    pub newroot: Option<PathBuf>,
+    /// Host directory mounted into containers as `/iguana`
+    pub iguana_dir: Option<PathBuf>,
+    /// Do not remove volumes created for jobs
+    pub keep_volumes: bool,
+    /// Memory limit of containers which do not set their own
+    pub memory: Option<String>,
+    /// CPU limit of containers which do not set their own
+    pub cpus: Option<f64>,
+    /// File receiving JSON report of the run
+    pub report: Option<PathBuf>,
+    /// File receiving state of the run for resuming it
+    pub state_file: Option<PathBuf>,
+    /// State file of the run to resume
+    pub resume: Option<PathBuf>,
+    /// Color job statuses in the summary
+    pub color: bool,
+    /// Format of the summary printed after the run
+    pub output_format: OutputFormat,
+    /// Canonical path of the workflow file, base of its includes
+    pub workflow_file: Option<PathBuf>,
+    /// Canonical paths of workflow files merged over the workflow in order
+    pub overlays: Vec<PathBuf>,
+    /// Directory relative paths in the workflow are resolved against, current one when not set
+    pub context: Option<PathBuf>,
+    /// Directory of the workflow file mounted into containers
+    pub workflow_dir: Option<PathBuf>,
+    /// How long single image pull may take, set by workflow
+    pub pull_timeout: Option<Duration>,
+    /// How many times image pull is tried before it fails, registry errors which do not
+    /// go away are not retried
+    pub pull_attempts: u32,
+    /// Delay before second pull attempt, doubled before each next one
+    pub pull_retry_delay: Duration,
+    /// Token identifying the run, set when workflow starts running
+    pub run_id: String,
 }
 
-pub fn do_workflow(workflow: String, opts: &WorkflowOptions) -> Result<(), String> {
-    let yaml_result: Result<Workflow, _> = serde_yaml::from_str(&workflow);
+impl WorkflowOptions {
+    /// Pull policy of container image, offline mode never pulls
+    pub fn effective_pull_policy(&self, container: &Container) -> PullPolicy {
+        if self.offline {
+            return PullPolicy::Never;
+        }
+        container.pull.unwrap_or(self.pull_policy)
+    }
 
-    let yaml = match yaml_result {
-        Ok(r) => r,
-        Err(e) => {
-            return Err(format!("Unable to parse provided workflow file: {}", e));
+    /// Path given in the workflow resolved against context directory, absolute paths
+    /// are kept
+    pub fn resolve_path(&self, path: &str) -> PathBuf {
+        match &self.context {
+            Some(context) => context.join(path.strip_prefix("./").unwrap_or(path)),
+            None => PathBuf::from(path),
         }
+    }
+}
+
+/// Order in which environmental variables are passed to container
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum EnvOrder {
+    /// Sorted by name, deterministic regardless of where variables are defined
+    Sorted,
+    /// In order of declaration, variable overridden by more specific env
+    /// is placed where the overriding declaration is
+    Declared,
+}
+
+/// Container runtime running the containers
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum Runtime {
+    Podman,
+    /// Registry credentials are not supported, images are pulled with docker configuration
+    Docker,
+}
+
+/// Format of the summary printed after the run
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Table of jobs in the log
+    Text,
+    /// JSON document with the same content as --report on standard output
+    Json,
+    /// Single `key=value` line per job on standard output
+    Kv,
+}
+
+/// Format of the workflow file
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum WorkflowFormat {
+    /// Decided by file extension, JSON for content starting with `{` otherwise
+    Auto,
+    Yaml,
+    Json,
+}
+
+impl WorkflowFormat {
+    /// Format given by extension of the workflow file, auto for unknown extensions
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => WorkflowFormat::Json,
+            Some("yaml") | Some("yml") => WorkflowFormat::Yaml,
+            _ => WorkflowFormat::Auto,
+        }
+    }
+}
+
+/// Restart policy of service containers
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    No,
+    OnFailure,
+    Always,
+}
+
+impl RestartPolicy {
+    /// Value of runtime `--restart` option
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RestartPolicy::No => "no",
+            RestartPolicy::OnFailure => "on-failure",
+            RestartPolicy::Always => "always",
+        }
+    }
+}
+
+/// Image pull policy
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PullPolicy {
+    /// Always pull image
+    Always,
+    /// Pull image only when not present locally
+    Missing,
+    /// Never pull, image must be present locally
+    Never,
+}
+
+/// Termination of timed out container
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum TimeoutAction {
+    /// Gracefully stop the container honoring its stop signal
+    Stop,
+    /// Kill the container immediately
+    Kill,
+}
+
+/// Receives notifications about workflow progress
+///
+/// All callbacks default to doing nothing, implementors override the ones they need.
+/// Jobs run in parallel, so callbacks may be called from multiple threads.
+pub trait RunObserver: Sync {
+    /// Job is about to be started
+    fn on_job_start(&self, _name: &str) {}
+    /// Job failed with given error, called before `on_job_finish`
+    fn on_job_error(&self, _name: &str, _error: &str) {}
+    /// Job finished or was skipped
+    fn on_job_finish(&self, _name: &str, _status: &JobStatus) {}
+    /// Step of the job is about to be run
+    fn on_step_start(&self, _job: &str, _step: &str) {}
+    /// Step of the job finished or was skipped after previous step failed
+    fn on_step_finish(&self, _job: &str, _step: &str, _status: &StepStatus) {}
+}
+
+/// Reorder jobs so that jobs listed in `order` run first and in given sequence,
+/// remaining jobs follow in file order
+fn apply_order(
+    mut jobs: LinkedHashMap<String, Job>,
+    order: &[String],
+) -> Result<LinkedHashMap<String, Job>, String> {
+    let mut ordered = LinkedHashMap::new();
+    for name in order.iter() {
+        if ordered.contains_key(name) {
+            return Err(format!("Job '{name}' is listed more than once in order"));
+        }
+        match jobs.remove(name) {
+            Some(job) => {
+                ordered.insert(name.to_owned(), job);
+            }
+            None => return Err(format!("Job '{name}' listed in order does not exist")),
+        }
+    }
+
+    // Listed jobs run before all unlisted ones, so everything they need must be listed earlier
+    let mut scheduled = HashSet::new();
+    for (name, job) in ordered.iter() {
+        for need in job.needed_jobs() {
+            if (ordered.contains_key(need) || jobs.contains_key(need)) && !scheduled.contains(need)
+            {
+                return Err(format!(
+                    "Order is not consistent with needs: job '{name}' needs '{need}' which is scheduled later"
+                ));
+            }
+        }
+        scheduled.insert(name);
+    }
+
+    ordered.extend(jobs);
+    Ok(ordered)
+}
+
+/// Deserialize workflow file in given format
+fn parse<T: DeserializeOwned>(workflow: &str, format: WorkflowFormat) -> Result<T, WorkflowError> {
+    let format = match format {
+        WorkflowFormat::Auto if workflow.trim_start().starts_with('{') => WorkflowFormat::Json,
+        WorkflowFormat::Auto => WorkflowFormat::Yaml,
+        f => f,
     };
+    match format {
+        WorkflowFormat::Json => serde_json::from_str(workflow)
+            .map_err(|e| WorkflowError::Parse(format!("Workflow file is not valid JSON: {e}"))),
+        _ => serde_yaml::from_str(workflow)
+            .map_err(|e| WorkflowError::Parse(format!("Workflow file is not valid YAML: {e}"))),
+    }
+}
+
+/// Check control file schema version is supported before parsing rest of the workflow
+///
+/// Control files without version are treated as the oldest supported version.
+fn check_version(workflow: &str, format: WorkflowFormat) -> Result<(), WorkflowError> {
+    let version = parse::<ControlVersion>(workflow, format)?
+        .version
+        .unwrap_or(MIN_CONTROL_VERSION);
+    if version > MAX_CONTROL_VERSION {
+        return Err(WorkflowError::Parse(format!(
+            "Control file version {version} is newer than supported version {MAX_CONTROL_VERSION}, please update iguana-workflow"
+        )));
+    }
+    if version < MIN_CONTROL_VERSION {
+        return Err(WorkflowError::Parse(format!(
+            "Control file version {version} is no longer supported, oldest supported version is {MIN_CONTROL_VERSION}"
+        )));
+    }
+    Ok(())
+}
 
-    info!("Loaded {}", yaml.name.unwrap_or("control file".to_owned()));
+/// Reorder jobs so that every job runs after all jobs it needs
+///
+/// Sort is stable, among jobs whose needs are satisfied the one earlier in current
+/// order runs first. Needs referencing jobs not in the workflow are ignored here.
+fn sort_by_needs(
+    mut jobs: LinkedHashMap<String, Job>,
+) -> Result<LinkedHashMap<String, Job>, String> {
+    let mut sorted = LinkedHashMap::new();
+    while !jobs.is_empty() {
+        let ready = jobs.iter().find(|(_, job)| {
+            job.run_after()
+                .all(|need| sorted.contains_key(need) || !jobs.contains_key(need))
+        });
+        let name = match ready {
+            Some((name, _)) => name.to_owned(),
+            None => {
+                let cycle: Vec<&str> = jobs.keys().map(|k| k.as_str()).collect();
+                return Err(format!(
+                    "Jobs can not be ordered because of circular needs: {}",
+                    cycle.join(", ")
+                ));
+            }
+        };
+        let job = jobs.remove(&name).expect("Ready job is in the workflow");
+        sorted.insert(name, job);
+    }
+    Ok(sorted)
+}
 
-    let jobs = yaml.jobs;
+/// Jobs transitively needed by named job, in the order in which they run
+fn needs_closure(jobs: &LinkedHashMap<String, Job>, name: &str) -> Result<Vec<String>, String> {
+    if !jobs.contains_key(name) {
+        return Err(format!("Job '{name}' is not part of the workflow"));
+    }
 
-    if jobs.is_empty() {
-        return Err("No jobs in control file!".to_owned());
+    let mut closure = HashSet::new();
+    let mut pending = vec![name];
+    while let Some(current) = pending.pop() {
+        for need in jobs[current].needed_jobs() {
+            if !jobs.contains_key(need) {
+                return Err(format!(
+                    "Job '{current}' needs '{need}' which is not part of the workflow"
+                ));
+            }
+            if need != name && closure.insert(need.as_str()) {
+                pending.push(need);
+            }
+        }
     }
 
-    let job_results = job::do_jobs(jobs, HashMap::new(), &yaml.env, opts);
+    Ok(jobs
+        .keys()
+        .filter(|j| closure.contains(j.as_str()))
+        .cloned()
+        .collect())
+}
+
+/// Named job and, with `with_deps`, jobs it needs
+fn named_jobs(
+    jobs: &LinkedHashMap<String, Job>,
+    name: &str,
+    with_deps: bool,
+) -> Result<HashSet<String>, String> {
+    if !jobs.contains_key(name) {
+        let available: Vec<&str> = jobs.keys().map(|j| j.as_str()).collect();
+        return Err(format!(
+            "Job '{name}' is not part of the workflow, available jobs: {}",
+            available.join(", ")
+        ));
+    }
+    let mut selected: HashSet<String> = HashSet::from([name.to_owned()]);
+    if with_deps {
+        selected.extend(needs_closure(jobs, name)?);
+    }
+    Ok(selected)
+}
 
-    match job_results {
-        Ok(_) => info!("Workflow ran successfully"),
-        Err(e) => return Err(e),
+/// Jobs with any of the tags and jobs they need
+fn tagged_jobs(
+    jobs: &LinkedHashMap<String, Job>,
+    tags: &[String],
+) -> Result<HashSet<String>, String> {
+    let mut selected = HashSet::new();
+    for tag in tags {
+        let tagged: Vec<&String> = jobs
+            .iter()
+            .filter(|(_, job)| job.tags.iter().flatten().any(|t| t == tag))
+            .map(|(name, _)| name)
+            .collect();
+        if tagged.is_empty() {
+            warn!("No job is tagged with '{tag}'");
+        }
+        for name in tagged {
+            selected.insert(name.to_owned());
+            selected.extend(needs_closure(jobs, name)?);
+        }
+    }
+    if selected.is_empty() {
+        return Err(format!("No job is tagged with any of {}", tags.join(", ")));
+    }
+    Ok(selected)
+}
+
+/// Keep only selected jobs
+///
+/// Selected jobs can not use containers of jobs which are not selected.
+fn select_jobs(
+    jobs: LinkedHashMap<String, Job>,
+    selected: &HashSet<String>,
+) -> Result<LinkedHashMap<String, Job>, String> {
+    for (j_name, job) in jobs.iter().filter(|(j, _)| selected.contains(*j)) {
+        for (c_name, container) in job.containers() {
+            match &container.from_job {
+                Some(from) if !selected.contains(from) => {
+                    return Err(format!(
+                        "Container '{c_name}' of job '{j_name}' is based on job '{from}' which is not selected to run"
+                    ))
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(jobs
+        .into_iter()
+        .filter(|(j, _)| selected.contains(j))
+        .collect())
+}
+
+/// Parse workflow and normalize it into the form in which it is run
+fn load_workflow(workflow: &str, opts: &WorkflowOptions) -> Result<Workflow, WorkflowError> {
+    check_version(workflow, opts.format)?;
+    duplicates::check_duplicates(workflow, opts.format)?;
+
+    let mut yaml: Workflow = parse(workflow, opts.format)?;
+
+    // Workflow itself overrides jobs and variables of included files
+    if let Some(includes) = &yaml.include {
+        let included = include::load_includes(
+            includes,
+            opts.workflow_file.as_deref(),
+            opts.context.as_deref(),
+        )
+        .map_err(WorkflowError::Parse)?;
+        let mut jobs = included.jobs;
+        jobs.extend(std::mem::take(&mut yaml.jobs));
+        yaml.jobs = jobs;
+        let mut env = included.env;
+        env.extend(yaml.env.take().unwrap_or_default());
+        yaml.env = Some(env);
+    }
+
+    // Workflow files given later on command line override earlier ones
+    for overlay in &opts.overlays {
+        let layer = include::load_overlay(overlay).map_err(WorkflowError::Parse)?;
+        for (name, job) in layer.jobs {
+            match yaml.jobs.get_mut(&name) {
+                Some(existing) => {
+                    info!(
+                        "Job {name} from {} overrides its earlier definition",
+                        overlay.display()
+                    );
+                    *existing = job;
+                }
+                None => {
+                    yaml.jobs.insert(name, job);
+                }
+            }
+        }
+        yaml.env
+            .get_or_insert_with(LinkedHashMap::new)
+            .extend(layer.env);
+    }
+
+    if yaml.jobs.is_empty() {
+        return Err(WorkflowError::Validation(
+            "No jobs in control file!".to_owned(),
+        ));
+    }
+
+    // Finally job is prepared as any other job and taken out of the graph at the end
+    let has_finally = yaml.finally.is_some();
+    if let Some(finally) = yaml.finally.take() {
+        check_finally(&yaml, &finally).map_err(WorkflowError::Validation)?;
+        yaml.jobs.insert(FINALLY_JOB.to_owned(), finally);
+    }
+
+    // Workflow defaults of containers
+    if let Some(defaults) = &yaml.defaults {
+        check_defaults(defaults).map_err(WorkflowError::Validation)?;
+    }
+    for (_, job) in yaml.jobs.iter_mut() {
+        let services = job.services.iter_mut().flat_map(|s| s.values_mut());
+        for container in std::iter::once(&mut job.container).chain(services) {
+            if let Some(defaults) = &yaml.defaults {
+                container.apply_defaults(defaults);
+            }
+            if container.pull.is_none() {
+                container.pull = yaml.pull;
+            }
+            if container.user.is_none() {
+                container.user.clone_from(&yaml.user);
+            }
+            if container.network.is_none() {
+                container.network.clone_from(&yaml.network);
+            }
+            if let Some(labels) = &yaml.labels {
+                let mut merged = labels.clone();
+                merged.extend(container.labels.take().unwrap_or_default());
+                container.labels = Some(merged);
+            }
+        }
+        // Service names which are valid hostnames become hostnames of their containers
+        for (s_name, service) in job.services.iter_mut().flatten() {
+            let host = service.network.as_deref().unwrap_or(HOST_NETWORK) == HOST_NETWORK;
+            if service.hostname.is_none() && !host && check_hostname(s_name).is_ok() {
+                service.hostname = Some(s_name.to_owned());
+            }
+        }
+    }
+
+    yaml.jobs =
+        matrix::expand_matrix(yaml.jobs, &mut yaml.order).map_err(WorkflowError::Validation)?;
+    validate(&yaml).map_err(WorkflowError::Validation)?;
+    resolve_service_networks(&mut yaml.jobs);
+    if let Some(order) = &yaml.order {
+        yaml.jobs = apply_order(yaml.jobs, order).map_err(WorkflowError::Validation)?;
+    }
+    yaml.jobs = sort_by_needs(yaml.jobs).map_err(WorkflowError::Validation)?;
+
+    if !opts.privileged {
+        check_privileged(&yaml.jobs).map_err(WorkflowError::Validation)?;
+    }
+
+    // Values passed on command line are used verbatim
+    interpolate::interpolate_env(&mut yaml.env).map_err(WorkflowError::Validation)?;
+    interpolate::interpolate_secrets(&mut yaml.secrets).map_err(WorkflowError::Validation)?;
+    merge_env_file(&mut yaml.env, yaml.env_file.as_deref(), opts).map_err(WorkflowError::Parse)?;
+    // Secrets are passed to containers as any other workflow variable
+    let mut env = yaml.env.unwrap_or_default();
+    if let Some(secrets) = &yaml.secrets {
+        env.extend(secrets.clone());
+    }
+    env.extend(opts.env.clone());
+    // Override layer is passed to containers separately, but references can use it too
+    let mut vars = env.clone();
+    vars.extend(opts.env_override.clone());
+    interpolate::interpolate_jobs(&mut yaml.jobs, &vars).map_err(WorkflowError::Validation)?;
+    // Expanded values are masked, they are what containers and logs see
+    if let Some(secrets) = &yaml.secrets {
+        secrets::register(secrets.values());
+    }
+    for job in yaml.jobs.values() {
+        if let Some(secrets) = &job.secrets {
+            secrets::register(secrets.values());
+        }
+    }
+    check_images(&yaml.jobs).map_err(WorkflowError::Validation)?;
+    load_env_files(&mut yaml.jobs, opts).map_err(WorkflowError::Parse)?;
+    if let Some(registries) = &mut yaml.registries {
+        if !registries.is_empty() && opts.runtime == Runtime::Docker {
+            return Err(WorkflowError::Validation(
+                "Registries credentials are not supported with docker runtime, use docker login"
+                    .to_owned(),
+            ));
+        }
+        interpolate::interpolate_registries(registries, &vars)
+            .map_err(WorkflowError::Validation)?;
+        check_registries(registries).map_err(WorkflowError::Validation)?;
+    }
+    yaml.env = Some(env);
+    if has_finally {
+        yaml.finally = yaml.jobs.remove(FINALLY_JOB);
+    }
+
+    Ok(yaml)
+}
+
+/// Parse `KEY=VALUE` lines into environment map
+///
+/// Empty lines and lines starting with `#` are ignored, values are used verbatim.
+pub fn parse_env_file(data: &str) -> Result<LinkedHashMap<String, String>, String> {
+    let mut env = LinkedHashMap::new();
+    for (i, line) in data.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        match line.split_once('=') {
+            Some((k, v)) if !k.trim().is_empty() => {
+                env.insert(k.trim().to_owned(), v.to_owned());
+            }
+            _ => return Err(format!("Line {} is not in KEY=VALUE format", i + 1)),
+        }
+    }
+    Ok(env)
+}
+
+/// Read environment file relative to context directory, see `parse_env_file`
+fn read_env_file(
+    path: &str,
+    opts: &WorkflowOptions,
+) -> Result<LinkedHashMap<String, String>, String> {
+    let env = match fs::read_to_string(opts.resolve_path(path)) {
+        Ok(data) => parse_env_file(&data).map_err(|e| format!("Env file {path}: {e}"))?,
+        Err(e) => return Err(format!("Unable to read env file {path}: {e}")),
+    };
+    let mut problems = Vec::new();
+    check_env_keys(&format!("Env file {path}"), Some(&env), &mut problems);
+    report_problems(problems)?;
+    Ok(env)
+}
+
+/// Merge variables of env file under inline environment, inline variables win
+fn merge_env_file(
+    env: &mut Option<LinkedHashMap<String, String>>,
+    path: Option<&str>,
+    opts: &WorkflowOptions,
+) -> Result<(), String> {
+    let path = match path {
+        Some(p) => p,
+        None => return Ok(()),
     };
+    let mut merged = read_env_file(path, opts)?;
+    merged.extend(env.take().unwrap_or_default());
+    *env = Some(merged);
+    Ok(())
+}
+
+/// Load env files of jobs and their containers, values are used verbatim
+fn load_env_files(
+    jobs: &mut LinkedHashMap<String, Job>,
+    opts: &WorkflowOptions,
+) -> Result<(), String> {
+    for (name, job) in jobs.iter_mut() {
+        if let Some(path) = &job.env_file {
+            job.file_env = read_env_file(path, opts).map_err(|e| format!("Job '{name}': {e}"))?;
+        }
+        merge_env_file(
+            &mut job.container.env,
+            job.container.env_file.as_deref(),
+            opts,
+        )
+        .map_err(|e| format!("Job '{name}' container: {e}"))?;
+        for (s_name, service) in job.services.iter_mut().flatten() {
+            merge_env_file(&mut service.env, service.env_file.as_deref(), opts)
+                .map_err(|e| format!("Job '{name}' service '{s_name}': {e}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Check containers based on other jobs reference existing jobs and do not specify image
+fn check_from_jobs(jobs: &LinkedHashMap<String, Job>, problems: &mut Vec<String>) {
+    for (name, job) in jobs.iter() {
+        for (c_name, container) in job.containers() {
+            match &container.from_job {
+                Some(from) => {
+                    if !container.image.is_empty() {
+                        problems.push(format!(
+                            "Container '{c_name}' of job '{name}' specifies both image and from_job"
+                        ));
+                    }
+                    if from == name || !jobs.contains_key(from) {
+                        problems.push(format!(
+                            "Container '{c_name}' of job '{name}' uses from_job '{from}' which is not another job of the workflow"
+                        ));
+                    } else if jobs[from].container.detach {
+                        problems.push(format!(
+                            "Container '{c_name}' of job '{name}' uses from_job '{from}' which runs detached"
+                        ));
+                    }
+                }
+                None if container.image.is_empty() => {
+                    problems.push(format!("Container '{c_name}' of job '{name}' has no image"))
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+/// Check every step either runs command or uses image and references only outputs of earlier steps
+fn check_steps(jobs: &LinkedHashMap<String, Job>, problems: &mut Vec<String>) {
+    for (name, job) in jobs.iter() {
+        let mut ids: Vec<&str> = Vec::new();
+        for (i, step) in job.all_steps().enumerate() {
+            let s_name = step.display_name(i);
+            match (&step.run, &step.uses) {
+                (Some(_), Some(_)) => problems.push(format!(
+                    "Step '{s_name}' of job '{name}' specifies both run and uses"
+                )),
+                (None, None) => problems.push(format!(
+                    "Step '{s_name}' of job '{name}' specifies neither run nor uses"
+                )),
+                _ => {}
+            }
+            if step.with.is_some() && step.uses.is_none() {
+                problems.push(format!(
+                    "Step '{s_name}' of job '{name}' specifies with but no uses"
+                ));
+            }
+            if step.shell.is_some() && step.run.is_none() {
+                problems.push(format!(
+                    "Step '{s_name}' of job '{name}' specifies shell but no run"
+                ));
+            }
+            if step.input.is_some() && step.run.is_none() {
+                problems.push(format!(
+                    "Step '{s_name}' of job '{name}' specifies input but no run"
+                ));
+            }
+
+            // Outputs can be referenced only from later steps of the same job
+            let values = step
+                .env
+                .iter()
+                .chain(step.with.iter())
+                .flat_map(|e| e.values());
+            for value in values {
+                match interpolate::step_references(value) {
+                    Ok(references) => {
+                        for (id, _) in references {
+                            if !ids.contains(&id) {
+                                problems.push(format!(
+                                    "Step '{s_name}' of job '{name}' references outputs of step '{id}', which is not an earlier step of the job"
+                                ));
+                            }
+                        }
+                    }
+                    Err(e) => problems.push(format!("Step '{s_name}' of job '{name}': {e}")),
+                }
+            }
+            if let Some(id) = &step.id {
+                if id.is_empty()
+                    || !id
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                {
+                    problems.push(format!(
+                        "Step '{s_name}' of job '{name}' has invalid id '{id}', only letters, digits, '_' and '-' are allowed"
+                    ));
+                } else if ids.contains(&id.as_str()) {
+                    problems.push(format!(
+                        "Step '{s_name}' of job '{name}' has id '{id}' used by another step"
+                    ));
+                }
+                ids.push(id);
+            }
+        }
+    }
+}
+
+/// Check needed jobs are part of the workflow
+fn check_needs(jobs: &LinkedHashMap<String, Job>, problems: &mut Vec<String>) {
+    for (name, job) in jobs.iter() {
+        for need in job.needed_jobs() {
+            if need == name {
+                problems.push(format!("Job '{name}' needs itself"));
+            } else if !jobs.contains_key(need) {
+                problems.push(format!(
+                    "Job '{name}' needs '{need}' which is not part of the workflow"
+                ));
+            }
+        }
+    }
+}
+
+/// Check names of all containers are unique, names are derived from job and service names
+fn check_container_names(jobs: &LinkedHashMap<String, Job>, problems: &mut Vec<String>) {
+    let mut names: HashMap<String, (&str, &str)> = HashMap::new();
+    for (name, job) in jobs.iter() {
+        if job
+            .services
+            .as_ref()
+            .is_some_and(|s| s.contains_key(job::MAIN_CONTAINER))
+        {
+            problems.push(format!(
+                "Service of job '{name}' can not be named '{}'",
+                job::MAIN_CONTAINER
+            ));
+            continue;
+        }
+        for (c_name, _) in job.containers() {
+            let container = job::container_name(name, c_name);
+            if let Some((other, other_c)) = names.insert(container.clone(), (name, c_name)) {
+                problems.push(format!(
+                    "Container '{c_name}' of job '{name}' and container '{other_c}' of job '{other}' have the same name {container}"
+                ));
+            }
+        }
+    }
+}
+
+/// Check volume is `target`, `source:target` or `source:target:options` with absolute
+/// target, source being host path starting with `/` or `.` or name of volume
+fn check_volume(volume: &str) -> Result<(), String> {
+    let parts: Vec<&str> = volume.split(':').collect();
+    if parts.len() > 3 || parts.iter().any(|p| p.is_empty()) {
+        return Err("expected [source:]target[:options]".to_owned());
+    }
+    let target = parts[parts.len().min(2) - 1];
+    if !target.starts_with('/') {
+        return Err(format!("target '{target}' is not absolute path"));
+    }
+    let source = parts[0];
+    let host = source.starts_with('/') || source.starts_with('.');
+    let named = source
+        .char_indices()
+        .all(|(i, c)| c.is_ascii_alphanumeric() || (i > 0 && "_.-".contains(c)));
+    if parts.len() > 1 && !host && !named {
+        return Err(format!(
+            "source '{source}' is neither host path nor valid volume name"
+        ));
+    }
+    if let Some(options) = parts.get(2) {
+        if options.split(',').any(|o| o.is_empty()) {
+            return Err(format!("options '{options}' contain empty option"));
+        }
+    }
     Ok(())
 }
+
+/// Check volumes of containers are well formed
+fn check_volumes(jobs: &LinkedHashMap<String, Job>, problems: &mut Vec<String>) {
+    for (name, job) in jobs.iter() {
+        for (c_name, container) in job.containers() {
+            for volume in container.volumes.iter().flatten() {
+                if let Err(e) = check_volume(volume) {
+                    problems.push(format!(
+                        "Volume '{volume}' of container '{c_name}' of job '{name}' is invalid: {e}"
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Check main containers joining network of a service join service of their job and do
+/// not set options owned by the service, services can not join other services
+fn check_service_networks(jobs: &LinkedHashMap<String, Job>, problems: &mut Vec<String>) {
+    for (name, job) in jobs.iter() {
+        for (c_name, container) in job.containers() {
+            let service = match container
+                .network
+                .as_deref()
+                .and_then(|n| n.strip_prefix(SERVICE_NETWORK_PREFIX))
+            {
+                Some(s) => s,
+                None => continue,
+            };
+            if c_name != job::MAIN_CONTAINER {
+                problems.push(format!(
+                    "Service '{c_name}' of job '{name}' joins network of service '{service}', only main container can join it"
+                ));
+                continue;
+            }
+            if !job
+                .services
+                .as_ref()
+                .is_some_and(|s| s.contains_key(service))
+            {
+                problems.push(format!(
+                    "Container '{c_name}' of job '{name}' joins network of service '{service}' which is not service of the job"
+                ));
+            }
+            let owned = [
+                ("ports", container.ports.is_some()),
+                ("hostname", container.hostname.is_some()),
+                ("extra_hosts", container.extra_hosts.is_some()),
+            ];
+            for (key, _) in owned.iter().filter(|(_, set)| *set) {
+                problems.push(format!(
+                    "Container '{c_name}' of job '{name}' joins network of service '{service}' and can not set {key}, set it on the service"
+                ));
+            }
+        }
+    }
+}
+
+/// Network of main containers joining network of a service refers to the service container
+fn resolve_service_networks(jobs: &mut LinkedHashMap<String, Job>) {
+    for (name, job) in jobs.iter_mut() {
+        let network = job.container.network.as_deref();
+        if let Some(service) = network.and_then(|n| n.strip_prefix(SERVICE_NETWORK_PREFIX)) {
+            let network = format!("container:{}", job::container_name(name, service));
+            job.container.network = Some(network);
+        }
+    }
+}
+
+/// Check containers publishing ports do not use host network
+fn check_ports(jobs: &LinkedHashMap<String, Job>, problems: &mut Vec<String>) {
+    for (name, job) in jobs.iter() {
+        for (c_name, container) in job.containers() {
+            let host = container.network.as_deref().unwrap_or(HOST_NETWORK) == HOST_NETWORK;
+            if host && container.ports.as_ref().is_some_and(|p| !p.is_empty()) {
+                problems.push(format!(
+                    "Container '{c_name}' of job '{name}' publishes ports, which requires network other than {HOST_NETWORK}"
+                ));
+            }
+        }
+    }
+}
+
+/// Check finally job does not depend on other jobs and no job depends on it
+fn check_finally(workflow: &Workflow, finally: &Job) -> Result<(), String> {
+    let mut problems = Vec::new();
+    if workflow.jobs.contains_key(FINALLY_JOB) {
+        problems.push(format!(
+            "Job '{FINALLY_JOB}' is reserved for the finally job"
+        ));
+    }
+    let unsupported = [
+        ("needs", finally.needs.is_some()),
+        ("if", finally.condition.is_some()),
+        ("matrix", finally.matrix.is_some()),
+        ("detach", finally.container.detach),
+        (
+            "from_job",
+            finally.containers().any(|(_, c)| c.from_job.is_some()),
+        ),
+    ];
+    for (key, _) in unsupported.iter().filter(|(_, set)| *set) {
+        problems.push(format!("Finally job can not set {key}"));
+    }
+    for (name, job) in workflow.jobs.iter() {
+        if job.run_after().any(|j| j == FINALLY_JOB) {
+            problems.push(format!("Job '{name}' can not depend on the finally job"));
+        }
+    }
+    if workflow.order.iter().flatten().any(|j| j == FINALLY_JOB) {
+        problems.push("Finally job can not be ordered".to_owned());
+    }
+    report_problems(problems)
+}
+
+/// Check workflow defaults set only settings which can be shared by all containers
+fn check_defaults(defaults: &Container) -> Result<(), String> {
+    let specific = [
+        ("image", !defaults.image.is_empty()),
+        ("from_job", defaults.from_job.is_some()),
+        ("hostname", defaults.hostname.is_some()),
+        ("command", defaults.command.is_some()),
+        ("restart", defaults.restart.is_some()),
+        ("healthcheck", defaults.healthcheck.is_some()),
+        ("detach", defaults.detach),
+        ("optional", defaults.optional),
+    ];
+    let problems = specific
+        .iter()
+        .filter(|(_, set)| *set)
+        .map(|(key, _)| format!("Workflow defaults can not set container {key}"))
+        .collect();
+    report_problems(problems)
+}
+
+/// Check hostname consists of valid DNS labels
+fn check_hostname(hostname: &str) -> Result<(), String> {
+    if hostname.len() > 253 {
+        return Err("hostname is longer than 253 characters".to_owned());
+    }
+    for label in hostname.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("'{label}' must have 1 to 63 characters"));
+        }
+        if !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(format!(
+                "'{label}' may contain only letters, digits and hyphens"
+            ));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(format!("'{label}' can not start or end with hyphen"));
+        }
+    }
+    Ok(())
+}
+
+/// Check GPUs of containers are `all`, positive count or list of device ids
+fn check_gpus(jobs: &LinkedHashMap<String, Job>, problems: &mut Vec<String>) {
+    for (name, job) in jobs.iter() {
+        for (c_name, container) in job.containers() {
+            let gpus = match &container.gpus {
+                Some(g) => g,
+                None => continue,
+            };
+            let valid = match gpus.strip_prefix("device=") {
+                Some(ids) => ids.split(',').all(|id| !id.trim().is_empty()),
+                None => gpus == "all" || gpus.parse::<u32>().is_ok_and(|n| n > 0),
+            };
+            if !valid {
+                problems.push(format!(
+                    "Container '{c_name}' of job '{name}' has invalid gpus '{gpus}', expected all, number of GPUs or device=<ids>"
+                ));
+            }
+        }
+    }
+}
+
+/// Check tmpfs mounts of containers are in `<absolute path>[:option,...]` format
+fn check_tmpfs(jobs: &LinkedHashMap<String, Job>, problems: &mut Vec<String>) {
+    for (name, job) in jobs.iter() {
+        for (c_name, container) in job.containers() {
+            for tmpfs in container.tmpfs.iter().flatten() {
+                let (path, options) = match tmpfs.split_once(':') {
+                    Some((p, o)) => (p, Some(o)),
+                    None => (tmpfs.as_str(), None),
+                };
+                let problem = if !path.starts_with('/') {
+                    "path must be absolute"
+                } else if options.is_some_and(|o| o.split(',').any(str::is_empty)) {
+                    "empty mount option"
+                } else {
+                    continue;
+                };
+                problems.push(format!(
+                    "Container '{c_name}' of job '{name}' has invalid tmpfs mount '{tmpfs}': {problem}"
+                ));
+            }
+        }
+    }
+}
+
+/// Check devices of containers are in `<host path>[:<container path>[:<permissions>]]`
+/// format, existence of host devices is checked when the container starts
+fn check_devices(jobs: &LinkedHashMap<String, Job>, problems: &mut Vec<String>) {
+    for (name, job) in jobs.iter() {
+        for (c_name, container) in job.containers() {
+            for device in container.devices.iter().flatten() {
+                let parts: Vec<&str> = device.split(':').collect();
+                // Permissions may be given without container path
+                let (target, permissions) = match parts[1..] {
+                    [] => (None, None),
+                    [p] if !p.starts_with('/') => (None, Some(p)),
+                    [t] => (Some(t), None),
+                    [t, p] => (Some(t), Some(p)),
+                    _ => (None, None),
+                };
+                let problem = if !parts[0].starts_with('/') {
+                    "host path must be absolute"
+                } else if parts.len() > 3 {
+                    "expected host path, container path and permissions"
+                } else if target.is_some_and(|t| !t.starts_with('/')) {
+                    "container path must be absolute"
+                } else if permissions
+                    .is_some_and(|p| p.is_empty() || !p.chars().all(|c| "rwm".contains(c)))
+                {
+                    "permissions must be combination of r, w and m"
+                } else {
+                    continue;
+                };
+                problems.push(format!(
+                    "Container '{c_name}' of job '{name}' has invalid device '{device}': {problem}"
+                ));
+            }
+        }
+    }
+}
+
+/// Check container hostnames are valid and not set on host network
+fn check_hostnames(jobs: &LinkedHashMap<String, Job>, problems: &mut Vec<String>) {
+    for (name, job) in jobs.iter() {
+        for (c_name, container) in job.containers() {
+            let hostname = match &container.hostname {
+                Some(h) => h,
+                None => continue,
+            };
+            if let Err(e) = check_hostname(hostname) {
+                problems.push(format!(
+                    "Container '{c_name}' of job '{name}' has invalid hostname '{hostname}': {e}"
+                ));
+            }
+            if container.network.as_deref().unwrap_or(HOST_NETWORK) == HOST_NETWORK {
+                problems.push(format!(
+                    "Container '{c_name}' of job '{name}' sets hostname, which requires network other than {HOST_NETWORK}"
+                ));
+            }
+        }
+    }
+}
+
+/// Check extra hosts of containers map valid hostnames to IP addresses
+fn check_extra_hosts(jobs: &LinkedHashMap<String, Job>, problems: &mut Vec<String>) {
+    for (name, job) in jobs.iter() {
+        for (c_name, container) in job.containers() {
+            for (host, ip) in container.extra_hosts.iter().flatten() {
+                if let Err(e) = check_hostname(host) {
+                    problems.push(format!(
+                        "Container '{c_name}' of job '{name}' has invalid extra host '{host}': {e}"
+                    ));
+                }
+                if ip.parse::<IpAddr>().is_err() {
+                    problems.push(format!(
+                        "Container '{c_name}' of job '{name}' maps extra host '{host}' to '{ip}', which is not IP address"
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Validate memory limit in `<number>[b|k|m|g]` format
+pub fn parse_memory(value: &str) -> Result<String, String> {
+    let number = value.trim_end_matches(|c: char| "bkmgBKMG".contains(c));
+    let valid = value.len() - number.len() <= 1
+        && number.parse::<f64>().is_ok_and(|n| n > 0.0)
+        && number.chars().all(|c| c.is_ascii_digit() || c == '.');
+    match valid {
+        true => Ok(value.to_owned()),
+        false => Err(format!(
+            "Invalid memory limit '{value}', expected number with optional b, k, m or g unit"
+        )),
+    }
+}
+
+/// Validate number of CPUs, fractions like `0.5` are allowed
+pub fn parse_cpus(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(cpus) if check_cpus(cpus) => Ok(cpus),
+        _ => Err(format!(
+            "Invalid number of CPUs '{value}', expected positive number"
+        )),
+    }
+}
+
+fn check_cpus(cpus: f64) -> bool {
+    cpus.is_finite() && cpus > 0.0
+}
+
+/// Check resource limits of containers are valid
+fn check_resources(jobs: &LinkedHashMap<String, Job>, problems: &mut Vec<String>) {
+    for (name, job) in jobs.iter() {
+        for (c_name, container) in job.containers() {
+            if let Some(Err(e)) = container.memory.as_deref().map(parse_memory) {
+                problems.push(format!("Container '{c_name}' of job '{name}': {e}"));
+            }
+            if let Some(cpus) = container.cpus.filter(|c| !check_cpus(*c)) {
+                problems.push(format!(
+                    "Container '{c_name}' of job '{name}': Invalid number of CPUs '{cpus}', expected positive number"
+                ));
+            }
+        }
+    }
+}
+
+/// Check only services set restart policy
+fn check_restart(jobs: &LinkedHashMap<String, Job>, problems: &mut Vec<String>) {
+    for (name, job) in jobs.iter() {
+        if job.container.restart.is_some() {
+            problems.push(format!(
+                "Container '{}' of job '{name}' sets restart policy, only services can be restarted",
+                job::MAIN_CONTAINER
+            ));
+        }
+    }
+}
+
+/// Check only services are optional, the job can not run without its main container
+fn check_optional(jobs: &LinkedHashMap<String, Job>, problems: &mut Vec<String>) {
+    for (name, job) in jobs.iter() {
+        if job.container.optional {
+            problems.push(format!(
+                "Container '{}' of job '{name}' is optional, only services can be optional",
+                job::MAIN_CONTAINER
+            ));
+        }
+    }
+}
+
+/// Variables iguana passes to containers itself, workflow can not set them
+const RESERVED_ENV: [&str; 8] = [
+    "iguana",
+    "IGUANA_OUTPUT",
+    "IGUANA_NEWROOT",
+    "IGUANA_RUN_ID",
+    "IGUANA_WORKFLOW_DIR",
+    "IGUANA_STEP_OUTPUT",
+    "IGUANA_WORKFLOW_RESULT",
+    "IGUANA_FAILED_JOBS",
+];
+
+/// Problem of variable name, unless it is letters, digits and underscores not starting
+/// with digit and not reserved by iguana
+pub fn env_key_problem(key: &str) -> Option<String> {
+    let valid = key
+        .char_indices()
+        .all(|(i, c)| c == '_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit()));
+    if key.is_empty() || !valid {
+        return Some(format!("invalid variable name '{key}'"));
+    }
+    if RESERVED_ENV.contains(&key) {
+        return Some(format!("variable '{key}' which is reserved by iguana"));
+    }
+    None
+}
+
+/// Check environmental variable names can be passed to containers
+fn check_env_keys(
+    what: &str,
+    env: Option<&LinkedHashMap<String, String>>,
+    problems: &mut Vec<String>,
+) {
+    for key in env.into_iter().flat_map(|e| e.keys()) {
+        if let Some(problem) = env_key_problem(key) {
+            problems.push(format!("{what} has {problem}"));
+        }
+    }
+}
+
+/// Check names of variables given outside of the workflow, e.g. on command line
+pub fn check_env_names(what: &str, env: &LinkedHashMap<String, String>) -> Result<(), String> {
+    let mut problems = Vec::new();
+    check_env_keys(what, Some(env), &mut problems);
+    report_problems(problems)
+}
+
+/// Check whole workflow and report all problems found at once
+fn validate(workflow: &Workflow) -> Result<(), String> {
+    let mut problems = Vec::new();
+    let jobs = &workflow.jobs;
+    check_from_jobs(jobs, &mut problems);
+    check_steps(jobs, &mut problems);
+    check_needs(jobs, &mut problems);
+    check_container_names(jobs, &mut problems);
+    check_volumes(jobs, &mut problems);
+    check_service_networks(jobs, &mut problems);
+    check_ports(jobs, &mut problems);
+    check_hostnames(jobs, &mut problems);
+    check_extra_hosts(jobs, &mut problems);
+    check_gpus(jobs, &mut problems);
+    check_tmpfs(jobs, &mut problems);
+    check_devices(jobs, &mut problems);
+    check_resources(jobs, &mut problems);
+    check_restart(jobs, &mut problems);
+    check_optional(jobs, &mut problems);
+
+    check_env_keys("Workflow env", workflow.env.as_ref(), &mut problems);
+    check_env_keys("Workflow secrets", workflow.secrets.as_ref(), &mut problems);
+    for (name, job) in jobs.iter() {
+        check_env_keys(
+            &format!("Job '{name}' secrets"),
+            job.secrets.as_ref(),
+            &mut problems,
+        );
+        for (c_name, container) in job.containers() {
+            check_env_keys(
+                &format!("Container '{c_name}' of job '{name}'"),
+                container.env.as_ref(),
+                &mut problems,
+            );
+        }
+        for (i, step) in job.all_steps().enumerate() {
+            check_env_keys(
+                &format!("Step '{}' of job '{name}'", step.display_name(i)),
+                step.env.as_ref(),
+                &mut problems,
+            );
+        }
+    }
+
+    report_problems(problems)
+}
+
+/// Check images of containers and steps are valid references once variables are expanded
+fn check_images(jobs: &LinkedHashMap<String, Job>) -> Result<(), String> {
+    let mut problems = Vec::new();
+    for (name, job) in jobs.iter() {
+        for (c_name, container) in job.containers() {
+            if container.from_job.is_some() {
+                continue;
+            }
+            if let Err(e) = reference::check_reference(&container.image) {
+                problems.push(format!(
+                    "Container '{c_name}' of job '{name}' has invalid image '{}': {e}",
+                    container.image
+                ));
+            }
+        }
+        for (i, step) in job.all_steps().enumerate() {
+            if step.local_action().is_some() {
+                continue;
+            }
+            if let Some(Err(e)) = step.uses.as_deref().map(reference::check_reference) {
+                problems.push(format!(
+                    "Step '{}' of job '{name}' uses invalid image '{}': {e}",
+                    step.display_name(i),
+                    step.uses.as_deref().unwrap_or_default()
+                ));
+            }
+        }
+    }
+    report_problems(problems)
+}
+
+/// Single error with all problems found in the workflow
+fn report_problems(mut problems: Vec<String>) -> Result<(), String> {
+    match problems.len() {
+        0 => Ok(()),
+        1 => Err(problems.remove(0)),
+        n => Err(format!(
+            "Workflow has {n} problems:\n  {}",
+            problems.join("\n  ")
+        )),
+    }
+}
+
+/// Check every registry has either username and password or authfile,
+/// passwords are masked in logs from now on
+fn check_registries(registries: &LinkedHashMap<String, Registry>) -> Result<(), String> {
+    for (name, registry) in registries.iter() {
+        match (&registry.username, &registry.password, &registry.authfile) {
+            (Some(_), Some(password), None) => secrets::register([password]),
+            (None, None, Some(_)) => {}
+            _ => {
+                return Err(format!(
+                    "Registry '{name}' must specify either username and password or authfile"
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Authfile with credentials of workflow registries added to the one from options
+fn registries_authfile(
+    registries: &LinkedHashMap<String, Registry>,
+    opts: &WorkflowOptions,
+) -> Result<Authfile, String> {
+    let credentials = registries.iter().map(|(name, registry)| {
+        let credentials = match (&registry.username, &registry.password, &registry.authfile) {
+            (Some(username), Some(password), _) => Credentials::Login { username, password },
+            (_, _, authfile) => {
+                Credentials::Authfile(Path::new(authfile.as_deref().unwrap_or_default()))
+            }
+        };
+        (name.as_str(), credentials)
+    });
+    auth::registries_authfile(opts.authfile.as_deref(), credentials)
+}
+
+/// Check no container depends on privileged mode when running unprivileged
+fn check_privileged(jobs: &LinkedHashMap<String, Job>) -> Result<(), String> {
+    let mut privileged = Vec::new();
+    for (name, job) in jobs.iter() {
+        for (c_name, container) in job.containers() {
+            if container.requires_privileged() {
+                privileged.push(format!("'{c_name}' of job '{name}'"));
+            }
+        }
+    }
+    if !privileged.is_empty() {
+        return Err(format!(
+            "Containers requiring privileged mode can not run with --unprivileged: {}",
+            privileged.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Token unique to this run, start time in nanoseconds and process id in hex
+fn run_id() -> String {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("{:x}-{:x}", started.as_nanos(), process::id())
+}
+
+/// Forwards events to other observer, remembering jobs in progress
+struct Progress<'a> {
+    inner: &'a dyn RunObserver,
+    running: Mutex<Vec<String>>,
+}
+
+impl RunObserver for Progress<'_> {
+    fn on_job_start(&self, name: &str) {
+        self.running.lock().unwrap().push(name.to_owned());
+        self.inner.on_job_start(name);
+    }
+    fn on_job_error(&self, name: &str, error: &str) {
+        self.inner.on_job_error(name, error);
+    }
+    fn on_job_finish(&self, name: &str, status: &JobStatus) {
+        self.running.lock().unwrap().retain(|j| j != name);
+        self.inner.on_job_finish(name, status);
+    }
+    fn on_step_start(&self, job: &str, step: &str) {
+        self.inner.on_step_start(job, step);
+    }
+    fn on_step_finish(&self, job: &str, step: &str, status: &StepStatus) {
+        self.inner.on_step_finish(job, step, status);
+    }
+}
+
+/// Run jobs on a worker, aborting them when they do not finish in time
+///
+/// Started containers are stopped on timeout, so the jobs fail and clean up after
+/// themselves. Jobs not started yet are not run.
+fn do_jobs_with_timeout(
+    jobs: LinkedHashMap<String, Job>,
+    resumed: HashMap<String, JobResult>,
+    env: &LinkedHashMap<String, String>,
+    timeout: Duration,
+    opts: &WorkflowOptions,
+    observer: &dyn RunObserver,
+) -> (HashMap<String, JobResult>, Result<(), WorkflowError>) {
+    let progress = Progress {
+        inner: observer,
+        running: Mutex::new(Vec::new()),
+    };
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+        let progress = &progress;
+        scope.spawn(move || {
+            let _ = tx.send(job::do_jobs(&Podman, jobs, resumed, env, opts, progress));
+        });
+        if let Ok(finished) = rx.recv_timeout(timeout) {
+            return finished;
+        }
+
+        let running = progress.running.lock().unwrap().join(", ");
+        let message = match running.is_empty() {
+            true => format!("Workflow timed out after {}s", timeout.as_secs()),
+            false => format!(
+                "Workflow timed out after {}s while running {running}",
+                timeout.as_secs()
+            ),
+        };
+        error!("{message}, stopping started containers");
+        interrupt::abort(opts);
+        let (jobs_status, _) = rx.recv().expect("Workflow worker exited without reporting");
+        (jobs_status, Err(WorkflowError::Timeout(message)))
+    })
+}
+
+/// Load workflow with the jobs selected to run and check it is valid, without running
+/// anything nor talking to container runtime
+pub fn validate_workflow(
+    workflow: &str,
+    opts: &WorkflowOptions,
+) -> Result<Workflow, WorkflowError> {
+    let mut yaml = load_workflow(workflow, opts)?;
+
+    info!("Loaded {}", yaml.name.as_deref().unwrap_or("control file"));
+    if let Some(description) = &yaml.description {
+        debug!("{description}");
+    }
+
+    // Jobs selected by name and by tags are run together
+    let mut selected = HashSet::new();
+    if let Some(name) = &opts.job {
+        selected.extend(
+            named_jobs(&yaml.jobs, name, opts.with_deps).map_err(WorkflowError::Validation)?,
+        );
+        info!(
+            "Running only job {name}{}",
+            if opts.with_deps {
+                " and jobs it needs"
+            } else {
+                ""
+            }
+        );
+    }
+    if !opts.tags.is_empty() {
+        selected.extend(tagged_jobs(&yaml.jobs, &opts.tags).map_err(WorkflowError::Validation)?);
+        info!(
+            "Running only jobs tagged with {} and jobs they need",
+            opts.tags.join(", ")
+        );
+    }
+    if opts.job.is_some() || !opts.tags.is_empty() {
+        yaml.jobs = select_jobs(yaml.jobs, &selected).map_err(WorkflowError::Validation)?;
+    }
+    Ok(yaml)
+}
+
+pub fn do_workflow(
+    workflow: String,
+    opts: &WorkflowOptions,
+    observer: &dyn RunObserver,
+) -> Result<HashMap<String, JobResult>, WorkflowError> {
+    let yaml = validate_workflow(&workflow, opts)?;
+
+    if opts.validate {
+        println!("Workflow is valid");
+        return Ok(HashMap::new());
+    }
+
+    if opts.tree {
+        print!("{}", tree::render_tree(&yaml));
+        return Ok(HashMap::new());
+    }
+
+    if opts.list {
+        print!("{}", tree::render_list(&yaml));
+        return Ok(HashMap::new());
+    }
+
+    if let Some(name) = &opts.needs_closure {
+        for need in needs_closure(&yaml.jobs, name).map_err(WorkflowError::Validation)? {
+            println!("{need}");
+        }
+        return Ok(HashMap::new());
+    }
+
+    // Keep registries authfile until the workflow finishes
+    let authfile = match &yaml.registries {
+        Some(registries) if !registries.is_empty() => {
+            Some(registries_authfile(registries, opts).map_err(WorkflowError::Io)?)
+        }
+        _ => None,
+    };
+    let opts = &WorkflowOptions {
+        authfile: match &authfile {
+            Some(authfile) => Some(authfile.path.clone()),
+            None => opts.authfile.clone(),
+        },
+        pull_timeout: yaml.pull_timeout.map(Duration::from_secs),
+        fail_fast: opts.fail_fast.or(yaml.fail_fast),
+        stop_timeout: opts.stop_timeout.or(yaml.stop_timeout),
+        run_id: run_id(),
+        ..opts.clone()
+    };
+    info!("Run ID {}", opts.run_id);
+
+    if opts.validate_images {
+        job::validate_images(&yaml.jobs, opts)?;
+        return Ok(HashMap::new());
+    }
+
+    if opts.offline {
+        job::check_local_images(&yaml.jobs, opts)?;
+    }
+
+    let env = yaml.env.unwrap_or_default();
+    if let Some(name) = &opts.debug_shell {
+        match yaml.jobs.get(name) {
+            Some(job) => job::debug_shell(name, job, &env, opts)?,
+            None => {
+                return Err(WorkflowError::Validation(format!(
+                    "Job '{name}' is not part of the workflow"
+                )))
+            }
+        }
+        return Ok(HashMap::new());
+    }
+
+    // Images may have changed since previous iteration, finally job shares this run's
+    podman::reset_pulled();
+    // Timeout of previous iteration must not abort this one
+    interrupt::reset();
+    let names: Vec<String> = yaml.jobs.keys().cloned().collect();
+    let start = Instant::now();
+    let resumed = match &opts.resume {
+        Some(path) => state::load_state(path, &yaml.jobs).map_err(WorkflowError::Io)?,
+        None => HashMap::new(),
+    };
+    let (mut jobs_status, mut result) = match opts.timeout {
+        Some(timeout) => do_jobs_with_timeout(yaml.jobs, resumed, &env, timeout, opts, observer),
+        None => job::do_jobs(&Podman, yaml.jobs, resumed, &env, opts, observer),
+    };
+
+    let mut missing = Vec::new();
+    for required in opts.required_jobs.iter() {
+        match jobs_status.get(required).map(|r| &r.status) {
+            Some(JobStatus::Success) => {}
+            Some(_) => missing.push(format!("'{required}' did not finish successfully")),
+            None => missing.push(format!("'{required}' is not part of the workflow")),
+        }
+    }
+    if result.is_ok() && !missing.is_empty() {
+        result = Err(WorkflowError::ContainerRun(format!(
+            "Required jobs failed: {}",
+            missing.join(", ")
+        )));
+    }
+
+    // Failure of finally job is reported, but the workflow result stays
+    let mut reported = names.clone();
+    if let Some(finally) = yaml.finally {
+        let mut env = env.clone();
+        let failed: Vec<&str> = names
+            .iter()
+            .filter(|j| {
+                jobs_status
+                    .get(*j)
+                    .is_some_and(|r| r.status == JobStatus::Failed)
+            })
+            .map(|j| j.as_str())
+            .collect();
+        let outcome = match result {
+            Ok(()) => "success",
+            Err(_) => "failure",
+        };
+        env.insert("IGUANA_WORKFLOW_RESULT".to_owned(), outcome.to_owned());
+        env.insert("IGUANA_FAILED_JOBS".to_owned(), failed.join(","));
+        let mut jobs = LinkedHashMap::new();
+        jobs.insert(FINALLY_JOB.to_owned(), finally);
+        let (finally_status, finally_result) =
+            job::do_jobs(&Podman, jobs, HashMap::new(), &env, opts, observer);
+        if let Err(e) = finally_result {
+            error!("Finally job failed: {e}");
+        }
+        jobs_status.extend(finally_status);
+        reported.push(FINALLY_JOB.to_owned());
+    }
+    let total = start.elapsed();
+
+    match opts.output_format {
+        OutputFormat::Text => summary::log_summary(&reported, &jobs_status, total, opts.color),
+        OutputFormat::Json => {
+            match report::render_report(&reported, &jobs_status, result.as_ref().err()) {
+                Ok(data) => print!("{data}"),
+                Err(e) => error!("{e}"),
+            }
+        }
+        OutputFormat::Kv => print!(
+            "{}",
+            summary::render_kv(&reported, &jobs_status, total, result.as_ref().err())
+        ),
+    }
+    if let Some(path) = &opts.report {
+        if let Err(e) = report::write_report(path, &reported, &jobs_status, result.as_ref().err()) {
+            error!("{e}");
+        }
+    }
+    // Resumed state is updated unless written elsewhere, dry run does not run jobs at all
+    let state_file = opts.state_file.as_ref().or(opts.resume.as_ref());
+    if let Some(path) = state_file.filter(|_| !opts.dry_run) {
+        if let Err(e) = state::write_state(path, &names, &jobs_status) {
+            error!("{e}");
+        }
+    }
+    result?;
+
+    info!("Workflow ran successfully");
+    Ok(jobs_status)
+}
@@ -0,0 +1,429 @@
+/// Conditions of jobs and steps given by `if` key
+///
+/// Expression consists of status functions `success()`, `failure()` and `always()`,
+/// comparisons `${NAME} == 'value'` and `!=`, combined by `!`, `&&`, `||` and parentheses.
+use linked_hash_map::LinkedHashMap;
+use serde::{Deserialize, Deserializer};
+
+use std::env;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Var(String),
+    Literal(String),
+    LParen,
+    RParen,
+    Not,
+    And,
+    Or,
+    Eq,
+    Ne,
+}
+
+#[derive(Clone)]
+enum Operand {
+    Var(String),
+    Literal(String),
+}
+
+#[derive(Clone)]
+enum Expr {
+    Success,
+    Failure,
+    Always,
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(Operand, Operand, bool),
+}
+
+/// State against which condition is evaluated
+pub struct Context<'a> {
+    /// Everything before succeeded, needed jobs for job, previous steps for step
+    pub success: bool,
+    /// Something before failed
+    pub failure: bool,
+    /// Variables referenced by comparisons, process environment is used for missing ones
+    pub env: &'a LinkedHashMap<String, String>,
+}
+
+/// Parsed condition
+#[derive(Clone)]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '!' if chars.peek() == Some(&'=') => {
+                chars.next();
+                Token::Ne
+            }
+            '!' => Token::Not,
+            '=' if chars.next() == Some('=') => Token::Eq,
+            '&' if chars.next() == Some('&') => Token::And,
+            '|' if chars.next() == Some('|') => Token::Or,
+            '\'' => {
+                let mut literal = String::new();
+                loop {
+                    match chars.next() {
+                        // Doubled quote stands for the quote itself
+                        Some('\'') if chars.peek() == Some(&'\'') => {
+                            chars.next();
+                            literal.push('\'');
+                        }
+                        Some('\'') => break,
+                        Some(c) => literal.push(c),
+                        None => return Err("unterminated string".to_owned()),
+                    }
+                }
+                Token::Literal(literal)
+            }
+            '$' if chars.next() == Some('{') => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err("unterminated variable reference".to_owned()),
+                    }
+                }
+                if name.is_empty() {
+                    return Err("empty variable reference".to_owned());
+                }
+                Token::Var(name)
+            }
+            c if c.is_ascii_alphabetic() => {
+                let mut ident = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_ascii_alphanumeric() && c != '_' {
+                        break;
+                    }
+                    ident.push(c);
+                    chars.next();
+                }
+                Token::Ident(ident)
+            }
+            c => return Err(format!("unexpected character '{c}'")),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser, `||` binds weaker than `&&`
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        match self.next() {
+            Some(Token::Var(name)) => Ok(Operand::Var(name)),
+            Some(Token::Literal(literal)) => Ok(Operand::Literal(literal)),
+            _ => Err("expected variable or string".to_owned()),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some(Token::LParen) => {
+                self.next();
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => Err("expected ')'".to_owned()),
+                }
+            }
+            Some(Token::Ident(function)) => {
+                let function = function.clone();
+                self.next();
+                if self.next() != Some(Token::LParen) || self.next() != Some(Token::RParen) {
+                    return Err(format!("expected '()' after '{function}'"));
+                }
+                match function.as_str() {
+                    "success" => Ok(Expr::Success),
+                    "failure" => Ok(Expr::Failure),
+                    "always" => Ok(Expr::Always),
+                    f => Err(format!("unknown function '{f}'")),
+                }
+            }
+            _ => {
+                let left = self.operand()?;
+                let negate = match self.next() {
+                    Some(Token::Eq) => false,
+                    Some(Token::Ne) => true,
+                    _ => return Err("expected '==' or '!='".to_owned()),
+                };
+                Ok(Expr::Compare(left, self.operand()?, negate))
+            }
+        }
+    }
+}
+
+impl Operand {
+    fn value(&self, context: &Context) -> String {
+        match self {
+            Operand::Literal(literal) => literal.to_owned(),
+            Operand::Var(name) => match context.env.get(name) {
+                Some(value) => value.to_owned(),
+                None => env::var(name).unwrap_or_default(),
+            },
+        }
+    }
+}
+
+impl Expr {
+    /// Whether result depends on status of what ran before
+    fn checks_status(&self) -> bool {
+        match self {
+            Expr::Success | Expr::Failure | Expr::Always => true,
+            Expr::Not(e) => e.checks_status(),
+            Expr::And(l, r) | Expr::Or(l, r) => l.checks_status() || r.checks_status(),
+            Expr::Compare(..) => false,
+        }
+    }
+
+    /// Whether the expression can hold after failure
+    fn handles_failure(&self) -> bool {
+        match self {
+            Expr::Failure | Expr::Always => true,
+            Expr::Success | Expr::Compare(..) => false,
+            // e.g. `!success()`
+            Expr::Not(e) => e.checks_status(),
+            Expr::And(l, r) | Expr::Or(l, r) => l.handles_failure() || r.handles_failure(),
+        }
+    }
+
+    fn evaluate(&self, context: &Context) -> bool {
+        match self {
+            Expr::Success => context.success,
+            Expr::Failure => context.failure,
+            Expr::Always => true,
+            Expr::Not(e) => !e.evaluate(context),
+            Expr::And(l, r) => l.evaluate(context) && r.evaluate(context),
+            Expr::Or(l, r) => l.evaluate(context) || r.evaluate(context),
+            Expr::Compare(l, r, negate) => (l.value(context) == r.value(context)) != *negate,
+        }
+    }
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            pos: 0,
+        };
+        let expr = parser.or()?;
+        if parser.peek().is_some() {
+            return Err(format!("unexpected {:?}", parser.tokens[parser.pos]));
+        }
+        Ok(Condition {
+            source: source.to_owned(),
+            expr,
+        })
+    }
+
+    /// Whether the condition decides itself about running after failure,
+    /// conditions without status function run only when everything before succeeded
+    pub fn checks_status(&self) -> bool {
+        self.expr.checks_status()
+    }
+
+    /// Whether the condition uses `failure()` or `always()`, so it is meant to run
+    /// after something failed
+    pub fn handles_failure(&self) -> bool {
+        self.expr.handles_failure()
+    }
+
+    pub fn evaluate(&self, context: &Context) -> bool {
+        if !self.checks_status() && !context.success {
+            return false;
+        }
+        self.expr.evaluate(context)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Condition {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        Condition::parse(&source)
+            .map_err(|e| serde::de::Error::custom(format!("invalid condition '{source}': {e}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Structure of parsed expression, e.g. `or(success, and(failure, always))`
+    fn shape(expr: &Expr) -> String {
+        match expr {
+            Expr::Success => "success".to_owned(),
+            Expr::Failure => "failure".to_owned(),
+            Expr::Always => "always".to_owned(),
+            Expr::Not(e) => format!("not({})", shape(e)),
+            Expr::And(l, r) => format!("and({}, {})", shape(l), shape(r)),
+            Expr::Or(l, r) => format!("or({}, {})", shape(l), shape(r)),
+            Expr::Compare(..) => "compare".to_owned(),
+        }
+    }
+
+    fn parsed(source: &str) -> String {
+        shape(&Condition::parse(source).unwrap().expr)
+    }
+
+    fn holds(source: &str, success: bool, env: &[(&str, &str)]) -> bool {
+        let env = env
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Condition::parse(source).unwrap().evaluate(&Context {
+            success,
+            failure: !success,
+            env: &env,
+        })
+    }
+
+    #[test]
+    fn and_binds_stronger_than_or() {
+        assert_eq!(
+            parsed("success() || failure() && always()"),
+            "or(success, and(failure, always))"
+        );
+        assert_eq!(
+            parsed("success() && failure() || always()"),
+            "or(and(success, failure), always)"
+        );
+    }
+
+    #[test]
+    fn combinators_are_left_associative() {
+        assert_eq!(
+            parsed("success() && failure() && always()"),
+            "and(and(success, failure), always)"
+        );
+        assert_eq!(
+            parsed("success() || failure() || always()"),
+            "or(or(success, failure), always)"
+        );
+    }
+
+    #[test]
+    fn parentheses_and_negation_nest() {
+        assert_eq!(
+            parsed("(success() || failure()) && always()"),
+            "and(or(success, failure), always)"
+        );
+        assert_eq!(
+            parsed("!success() && !(failure() || always())"),
+            "and(not(success), not(or(failure, always)))"
+        );
+        assert_eq!(parsed("((success()))"), "success");
+    }
+
+    #[test]
+    fn incomplete_combinators_are_rejected() {
+        for source in [
+            "success() &&",
+            "|| failure()",
+            "(success() || failure()",
+            "success() & failure()",
+        ] {
+            assert!(Condition::parse(source).is_err(), "{source} parsed");
+        }
+    }
+
+    #[test]
+    fn and_holds_when_both_hold() {
+        let env = [("A", "1"), ("B", "2")];
+        assert!(holds("${A} == '1' && ${B} == '2'", true, &env));
+        assert!(!holds("${A} == '1' && ${B} == '3'", true, &env));
+        assert!(!holds("${A} == '0' && ${B} == '2'", true, &env));
+    }
+
+    #[test]
+    fn or_holds_when_either_holds() {
+        let env = [("A", "1"), ("B", "2")];
+        assert!(holds("${A} == '0' || ${B} == '2'", true, &env));
+        assert!(holds("${A} == '1' || ${B} == '0'", true, &env));
+        assert!(!holds("${A} == '0' || ${B} == '0'", true, &env));
+    }
+
+    #[test]
+    fn precedence_decides_evaluation() {
+        // true || (false && false) holds, (true || false) && false would not
+        let env = [("A", "1")];
+        assert!(holds(
+            "${A} == '1' || ${A} == '2' && ${A} == '3'",
+            true,
+            &env
+        ));
+        assert!(!holds(
+            "(${A} == '1' || ${A} == '2') && ${A} == '3'",
+            true,
+            &env
+        ));
+    }
+
+    #[test]
+    fn status_functions_combine_after_failure() {
+        assert!(holds("failure() || success()", false, &[]));
+        assert!(!holds("failure() && success()", false, &[]));
+        assert!(holds("always() && !success()", false, &[]));
+        // Condition without status function does not run after failure
+        assert!(!holds("${A} == '' || ${A} == 'x'", false, &[]));
+    }
+}
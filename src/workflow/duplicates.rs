@@ -0,0 +1,88 @@
+/// Detection of job and service names given more than once, which parsers silently collapse
+use serde::de::{Deserializer, IgnoredAny, MapAccess, Visitor};
+use serde::Deserialize;
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use crate::workflow::{parse, report_problems, WorkflowError, WorkflowFormat};
+
+/// Map entries in the order they are written, duplicate keys included
+struct Entries<V>(Vec<(String, V)>);
+
+impl<V> Default for Entries<V> {
+    fn default() -> Self {
+        Entries(Vec::new())
+    }
+}
+
+impl<'de, V: Deserialize<'de>> Deserialize<'de> for Entries<V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntriesVisitor<V>(PhantomData<V>);
+
+        impl<'de, V: Deserialize<'de>> Visitor<'de> for EntriesVisitor<V> {
+            type Value = Entries<V>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a map")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut entries = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(Entries(entries))
+            }
+        }
+
+        deserializer.deserialize_map(EntriesVisitor(PhantomData))
+    }
+}
+
+impl<V> Entries<V> {
+    /// Keys given more than once, each reported once in order of first repetition
+    fn duplicates(&self) -> Vec<&str> {
+        let mut duplicates = Vec::new();
+        for (i, (key, _)) in self.0.iter().enumerate() {
+            if self.0[..i].iter().any(|(k, _)| k == key) && !duplicates.contains(&key.as_str()) {
+                duplicates.push(key.as_str());
+            }
+        }
+        duplicates
+    }
+}
+
+/// Service names of a job as written in control file
+#[derive(Deserialize)]
+struct JobNames {
+    #[serde(default)]
+    services: Option<Entries<IgnoredAny>>,
+}
+
+/// Job names as written in control file
+#[derive(Deserialize)]
+struct ControlNames {
+    #[serde(default)]
+    jobs: Entries<JobNames>,
+}
+
+/// Check control file does not define any job or service of a job twice
+pub fn check_duplicates(workflow: &str, format: WorkflowFormat) -> Result<(), WorkflowError> {
+    let names = parse::<ControlNames>(workflow, format)?;
+    let mut problems: Vec<String> = names
+        .jobs
+        .duplicates()
+        .iter()
+        .map(|j| format!("Job '{j}' is defined more than once"))
+        .collect();
+    for (name, job) in names.jobs.0.iter() {
+        for service in job.services.iter().flat_map(|s| s.duplicates()) {
+            let problem = format!("Service '{service}' of job '{name}' is defined more than once");
+            if !problems.contains(&problem) {
+                problems.push(problem);
+            }
+        }
+    }
+    report_problems(problems).map_err(WorkflowError::Validation)
+}
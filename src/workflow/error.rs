@@ -0,0 +1,82 @@
+/// Errors of loading and running workflow
+use std::error::Error;
+use std::fmt;
+
+/// Kind of failure together with message describing it
+#[derive(Clone, Debug)]
+pub enum WorkflowError {
+    /// Workflow or file it references can not be read or parsed
+    Parse(String),
+    /// Workflow is well formed but its content is invalid
+    Validation(String),
+    /// Container image can not be pulled or is not available locally
+    ImagePull(String),
+    /// Container image does not exist, access to it is denied or it may not be pulled,
+    /// pulling it again does not help
+    ImageUnavailable(String),
+    /// Container runtime failed, or job or its container did not succeed
+    ContainerRun(String),
+    /// Job or step did not finish in time
+    Timeout(String),
+    /// Host file used by the run can not be written
+    Io(String),
+}
+
+impl WorkflowError {
+    pub fn message(&self) -> &str {
+        match self {
+            WorkflowError::Parse(m)
+            | WorkflowError::Validation(m)
+            | WorkflowError::ImagePull(m)
+            | WorkflowError::ImageUnavailable(m)
+            | WorkflowError::ContainerRun(m)
+            | WorkflowError::Timeout(m)
+            | WorkflowError::Io(m) => m,
+        }
+    }
+
+    /// Name of the error kind, e.g. in JSON report
+    pub fn kind(&self) -> &'static str {
+        match self {
+            WorkflowError::Parse(_) => "parse",
+            WorkflowError::Validation(_) => "validation",
+            WorkflowError::ImagePull(_) | WorkflowError::ImageUnavailable(_) => "image_pull",
+            WorkflowError::ContainerRun(_) => "container_run",
+            WorkflowError::Timeout(_) => "timeout",
+            WorkflowError::Io(_) => "io",
+        }
+    }
+
+    /// Whether running the job again can not succeed, as the workflow itself is wrong
+    /// or its image can not be had
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            self,
+            WorkflowError::Parse(_)
+                | WorkflowError::Validation(_)
+                | WorkflowError::ImageUnavailable(_)
+        )
+    }
+
+    /// Error of the same kind with message prefixed by context, `context: message`
+    pub fn context(self, context: &str) -> Self {
+        let wrap = |m: String| format!("{context}: {m}");
+        match self {
+            WorkflowError::Parse(m) => WorkflowError::Parse(wrap(m)),
+            WorkflowError::Validation(m) => WorkflowError::Validation(wrap(m)),
+            WorkflowError::ImagePull(m) => WorkflowError::ImagePull(wrap(m)),
+            WorkflowError::ImageUnavailable(m) => WorkflowError::ImageUnavailable(wrap(m)),
+            WorkflowError::ContainerRun(m) => WorkflowError::ContainerRun(wrap(m)),
+            WorkflowError::Timeout(m) => WorkflowError::Timeout(wrap(m)),
+            WorkflowError::Io(m) => WorkflowError::Io(wrap(m)),
+        }
+    }
+}
+
+impl fmt::Display for WorkflowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl Error for WorkflowError {}
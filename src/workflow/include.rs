@@ -0,0 +1,96 @@
+/// Merging of jobs and environment defined in included workflow files
+use linked_hash_map::LinkedHashMap;
+use serde::Deserialize;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::workflow::duplicates::check_duplicates;
+use crate::workflow::{check_version, parse, Job, WorkflowFormat};
+
+/// Part of included workflow file merged into the including workflow
+#[derive(Deserialize)]
+struct Fragment {
+    include: Option<Vec<String>>,
+    #[serde(default)]
+    jobs: LinkedHashMap<String, Job>,
+    env: Option<LinkedHashMap<String, String>>,
+}
+
+/// Jobs and environment of all included files
+#[derive(Default)]
+pub struct Included {
+    pub jobs: LinkedHashMap<String, Job>,
+    pub env: LinkedHashMap<String, String>,
+}
+
+/// Absolute path of included file, relative paths are relative to directory of the including file
+fn resolve(include: &str, base: &Path) -> Result<PathBuf, String> {
+    if include.contains("://") {
+        return Err(format!(
+            "Unable to include {include}, only local files can be included"
+        ));
+    }
+    let path = base.join(include);
+    fs::canonicalize(&path).map_err(|e| format!("Unable to include {}: {e}", path.display()))
+}
+
+fn load(
+    includes: &[String],
+    base: &Path,
+    stack: &mut Vec<PathBuf>,
+    included: &mut Included,
+) -> Result<(), String> {
+    for include in includes {
+        let path = resolve(include, base)?;
+        if stack.contains(&path) {
+            return Err(format!("Cyclic include of {}", path.display()));
+        }
+        let data = match fs::read_to_string(&path) {
+            Ok(d) => d,
+            Err(e) => return Err(format!("Unable to include {}: {e}", path.display())),
+        };
+        let format = WorkflowFormat::from_path(&path);
+        let fragment: Fragment = check_version(&data, format)
+            .and_then(|_| check_duplicates(&data, format))
+            .and_then(|_| parse(&data, format))
+            .map_err(|e| format!("Included file {}: {e}", path.display()))?;
+
+        // Nested includes come before the file itself, so the file overrides them
+        if let Some(nested) = &fragment.include {
+            let dir = path.parent().unwrap_or(Path::new("/")).to_owned();
+            stack.push(path);
+            load(nested, &dir, stack, included)?;
+            stack.pop();
+        }
+        included.jobs.extend(fragment.jobs);
+        included.env.extend(fragment.env.unwrap_or_default());
+    }
+    Ok(())
+}
+
+/// Load jobs and environment of workflow file given on command line over the workflow,
+/// including files it includes
+pub fn load_overlay(path: &Path) -> Result<Included, String> {
+    let mut included = Included::default();
+    let file = path.to_string_lossy().into_owned();
+    load(&[file], Path::new("/"), &mut Vec::new(), &mut included)?;
+    Ok(included)
+}
+
+/// Load jobs and environment of included files in order, later files override earlier ones
+///
+/// Includes of the workflow are relative to context directory, nested includes to the
+/// including file. Including a file which is already being included, directly or through
+/// other files, is an error.
+pub fn load_includes(
+    includes: &[String],
+    workflow_file: Option<&Path>,
+    context: Option<&Path>,
+) -> Result<Included, String> {
+    let base = context.unwrap_or(Path::new("."));
+    let mut stack: Vec<PathBuf> = workflow_file.map(Path::to_owned).into_iter().collect();
+    let mut included = Included::default();
+    load(includes, base, &mut stack, &mut included)?;
+    Ok(included)
+}
@@ -0,0 +1,228 @@
+/// Expansion of `${NAME}` and `$NAME` variable references in workflow values
+use linked_hash_map::LinkedHashMap;
+use log::warn;
+
+use std::collections::HashMap;
+use std::env;
+
+use crate::workflow::{Container, Job, Registry};
+
+/// Prefix of references to outputs of earlier steps, expanded only when the step runs
+const STEP_REFERENCE_PREFIX: &str = "steps.";
+
+/// Value of variable from workflow environment, falling back to process environment
+fn lookup(name: &str, vars: &LinkedHashMap<String, String>) -> Result<String, String> {
+    match vars.get(name) {
+        Some(v) => Ok(v.to_owned()),
+        None => env::var(name).map_err(|_| format!("Undefined variable '{name}'")),
+    }
+}
+
+/// Expand variable references in value, `$$` is literal `$`
+///
+/// `$` not followed by variable name, `{` or another `$` is kept as it is.
+pub fn expand(value: &str, vars: &LinkedHashMap<String, String>) -> Result<String, String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.peek() {
+            Some('$') => {
+                chars.next();
+                expanded.push('$');
+            }
+            Some('{') => {
+                chars.next();
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => {
+                            return Err(format!("Unterminated variable reference in '{value}'"))
+                        }
+                    }
+                }
+                if name.is_empty() {
+                    return Err(format!("Empty variable reference in '{value}'"));
+                }
+                if name.starts_with(STEP_REFERENCE_PREFIX) {
+                    expanded.push_str(&format!("${{{name}}}"));
+                    continue;
+                }
+                expanded.push_str(&lookup(&name, vars)?);
+            }
+            Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+                let mut name = String::new();
+                while let Some(c) = chars.peek() {
+                    if !c.is_ascii_alphanumeric() && *c != '_' {
+                        break;
+                    }
+                    name.push(*c);
+                    chars.next();
+                }
+                expanded.push_str(&lookup(&name, vars)?);
+            }
+            _ => expanded.push('$'),
+        }
+    }
+    Ok(expanded)
+}
+
+fn expand_env(
+    env: &mut Option<LinkedHashMap<String, String>>,
+    vars: &LinkedHashMap<String, String>,
+) -> Result<(), String> {
+    for (_, value) in env.iter_mut().flat_map(|e| e.iter_mut()) {
+        *value = expand(value, vars)?;
+    }
+    Ok(())
+}
+
+fn expand_container(
+    container: &mut Container,
+    vars: &LinkedHashMap<String, String>,
+) -> Result<(), String> {
+    container.image = expand(&container.image, vars)?;
+    if let Some(dir) = &container.working_directory {
+        container.working_directory = Some(expand(dir, vars)?);
+    }
+    expand_env(&mut container.labels, vars)?;
+    expand_env(&mut container.env, vars)
+}
+
+/// Expand variables in workflow environment using process environment
+pub fn interpolate_env(env: &mut Option<LinkedHashMap<String, String>>) -> Result<(), String> {
+    expand_env(env, &LinkedHashMap::new()).map_err(|e| format!("Workflow env: {e}"))
+}
+
+/// Expand variables in workflow secrets using process environment, e.g. CI tokens
+pub fn interpolate_secrets(
+    secrets: &mut Option<LinkedHashMap<String, String>>,
+) -> Result<(), String> {
+    expand_env(secrets, &LinkedHashMap::new()).map_err(|e| format!("Workflow secrets: {e}"))
+}
+
+/// Expand variables in container images, labels, job secrets and container and step
+/// environment
+///
+/// Variables are looked up in given workflow environment first, then in process environment.
+pub fn interpolate_jobs(
+    jobs: &mut LinkedHashMap<String, Job>,
+    vars: &LinkedHashMap<String, String>,
+) -> Result<(), String> {
+    for (name, job) in jobs.iter_mut() {
+        expand_env(&mut job.secrets, vars).map_err(|e| format!("Job '{name}' secrets: {e}"))?;
+        expand_container(&mut job.container, vars)
+            .map_err(|e| format!("Job '{name}' container: {e}"))?;
+        for (s_name, service) in job.services.iter_mut().flatten() {
+            expand_container(service, vars)
+                .map_err(|e| format!("Job '{name}' service '{s_name}': {e}"))?;
+        }
+        for (i, step) in job.all_steps_mut().enumerate() {
+            let s_name = step.display_name(i);
+            if let Some(dir) = &step.working_directory {
+                step.working_directory = Some(
+                    expand(dir, vars).map_err(|e| format!("Job '{name}' step '{s_name}': {e}"))?,
+                );
+            }
+            expand_env(&mut step.env, vars)
+                .map_err(|e| format!("Job '{name}' step '{s_name}': {e}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Interpolate registry credentials, so they can be passed as variables or secrets
+pub fn interpolate_registries(
+    registries: &mut LinkedHashMap<String, Registry>,
+    vars: &LinkedHashMap<String, String>,
+) -> Result<(), String> {
+    for (name, registry) in registries.iter_mut() {
+        for value in [
+            &mut registry.username,
+            &mut registry.password,
+            &mut registry.authfile,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *value = expand(value, vars).map_err(|e| format!("Registry '{name}': {e}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Step id and output key of `steps.<id>.outputs.<key>` reference
+fn parse_step_reference(reference: &str) -> Result<(&str, &str), String> {
+    let rest = reference
+        .strip_prefix(STEP_REFERENCE_PREFIX)
+        .unwrap_or_default();
+    match rest.split_once(".outputs.") {
+        Some((id, key)) if !id.is_empty() && !key.is_empty() => Ok((id, key)),
+        _ => Err(format!(
+            "Invalid step output reference '{reference}', expected steps.<id>.outputs.<key>"
+        )),
+    }
+}
+
+/// Step ids and output keys of all `${steps.<id>.outputs.<key>}` references in value
+pub fn step_references(value: &str) -> Result<Vec<(&str, &str)>, String> {
+    let mut references = Vec::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let after = &rest[start + 2..];
+        let end = match after.find('}') {
+            Some(e) => e,
+            None => break,
+        };
+        let name = &after[..end];
+        if name.starts_with(STEP_REFERENCE_PREFIX) {
+            references.push(parse_step_reference(name)?);
+        }
+        rest = &after[end + 1..];
+    }
+    Ok(references)
+}
+
+/// Expand `${steps.<id>.outputs.<key>}` references left by workflow interpolation
+///
+/// Steps which did not run successfully have no outputs, references to them expand
+/// to empty string. Referencing output never written by step which ran is an error.
+pub fn expand_step_outputs(
+    value: &str,
+    outputs: &HashMap<String, Option<LinkedHashMap<String, String>>>,
+) -> Result<String, String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let after = &rest[start + 2..];
+        let end = match after.find('}') {
+            Some(e) => e,
+            None => break,
+        };
+        let name = &after[..end];
+        expanded.push_str(&rest[..start]);
+        if name.starts_with(STEP_REFERENCE_PREFIX) {
+            let (id, key) = parse_step_reference(name)?;
+            match outputs.get(id) {
+                Some(Some(step_outputs)) => match step_outputs.get(key) {
+                    Some(v) => expanded.push_str(v),
+                    None => return Err(format!("Step '{id}' has no output '{key}'")),
+                },
+                Some(None) => {
+                    warn!("Step '{id}' did not succeed, its output '{key}' is empty")
+                }
+                None => return Err(format!("Step '{id}' has not run yet")),
+            }
+        } else {
+            expanded.push_str(&rest[start..start + 2 + end + 1]);
+        }
+        rest = &after[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
@@ -1,16 +1,29 @@
 /// Implementation of job execution
 use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::{mpsc, Mutex};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
 
 use linked_hash_map::LinkedHashMap;
-use log::{debug, error, warn};
+use log::{debug, error, info, warn};
 
-use crate::engines::{ContainerOps, ImageOps, VolumeOps};
-use crate::workflow::{Job, WorkflowOptions};
+use crate::engines::{ContainerExec, ContainerOps, ContainerRun, Engine, ImageOps};
+use crate::interrupt;
+use crate::workflow::condition::Context;
+use crate::workflow::interpolate::expand_step_outputs;
+use crate::workflow::{
+    Container, Healthcheck, Job, Needs, RunObserver, Step, WorkflowError, WorkflowOptions,
+};
 
-use crate::engines::podman::Podman;
+use crate::engines::podman::{Podman, ACTION_TARGET, STEP_OUTPUTS_TARGET};
 
 /// Available results of container run
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum JobStatus {
     NoStatus,
     Skipped,
@@ -18,189 +31,1711 @@ pub enum JobStatus {
     Failed,
 }
 
-fn merge_from_ref(map: &mut HashMap<String, String>, map2: &HashMap<String, String>) {
-    map.extend(map2.into_iter().map(|(k, v)| (k.clone(), v.clone())));
+impl fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self {
+            JobStatus::NoStatus => "not run",
+            JobStatus::Skipped => "skipped",
+            JobStatus::Success => "success",
+            JobStatus::Failed => "failed",
+        };
+        write!(f, "{status}")
+    }
 }
 
-fn do_job(
-    name: &String,
+/// Result of a step run
+#[derive(Clone, Copy, PartialEq)]
+pub enum StepStatus {
+    /// Step did not run, because earlier step failed or its condition did not hold
+    Skipped,
+    Success,
+    /// Step failed, job fails too unless the step continues on error
+    Failed,
+}
+
+impl fmt::Display for StepStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self {
+            StepStatus::Skipped => "skipped",
+            StepStatus::Success => "success",
+            StepStatus::Failed => "failed",
+        };
+        write!(f, "{status}")
+    }
+}
+
+/// Result of a job run
+pub struct JobResult {
+    pub status: JobStatus,
+    /// Exit code of the job container, if it ran and exited normally
+    pub exit_code: Option<i32>,
+    /// How long the job ran, including retries and cleanup
+    pub duration: Option<Duration>,
+    /// Error of failed job or reason why job was skipped
+    pub error: Option<String>,
+    /// Names and results of steps in order they ran, steps of all attempts of retried job
+    pub steps: Vec<(String, StepStatus)>,
+    /// Outputs written by successful job
+    pub outputs: LinkedHashMap<String, String>,
+}
+
+impl JobResult {
+    fn new(status: JobStatus) -> Self {
+        JobResult {
+            status,
+            exit_code: None,
+            duration: None,
+            error: None,
+            steps: Vec::new(),
+            outputs: LinkedHashMap::new(),
+        }
+    }
+}
+
+/// Observer passing events on while recording results of steps of single job
+struct StepRecorder<'a> {
+    inner: &'a dyn RunObserver,
+    steps: Mutex<Vec<(String, StepStatus)>>,
+}
+
+impl RunObserver for StepRecorder<'_> {
+    fn on_job_start(&self, name: &str) {
+        self.inner.on_job_start(name)
+    }
+
+    fn on_job_error(&self, name: &str, error: &str) {
+        self.inner.on_job_error(name, error)
+    }
+
+    fn on_job_finish(&self, name: &str, status: &JobStatus) {
+        self.inner.on_job_finish(name, status)
+    }
+
+    fn on_step_start(&self, job: &str, step: &str) {
+        self.inner.on_step_start(job, step)
+    }
+
+    fn on_step_finish(&self, job: &str, step: &str, status: &StepStatus) {
+        self.steps
+            .lock()
+            .expect("Steps lock poisoned")
+            .push((step.to_owned(), *status));
+        self.inner.on_step_finish(job, step, status)
+    }
+}
+
+/// Display name of the job's main container, services use their map key
+pub const MAIN_CONTAINER: &str = "main";
+
+/// Shell started by `--debug-shell` instead of job container entrypoint
+const DEBUG_SHELL: &str = "/bin/sh";
+
+/// Shell running step commands which do not select their own
+const DEFAULT_SHELL: &str = "/bin/sh";
+
+/// Podman container name for given job container
+///
+/// Characters not allowed in container names are replaced by underscore
+pub fn container_name(job: &str, container: &str) -> String {
+    format!("iguana-{job}-{container}")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Local image name for committed main container of given job
+fn committed_image(job: &str) -> String {
+    let name: String = job
+        .to_lowercase()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("localhost/iguana-commit-{name}")
+}
+
+/// Image to run for given container, resolving images committed from other jobs
+fn resolve_image<'a>(
+    container: &'a Container,
+    committed: &'a HashMap<String, String>,
+) -> Result<&'a str, String> {
+    match &container.from_job {
+        Some(from) => match committed.get(from) {
+            Some(image) => Ok(image),
+            None => Err(format!(
+                "Job '{from}' did not provide committed image, it must finish successfully first"
+            )),
+        },
+        None => Ok(&container.image),
+    }
+}
+
+fn merge_from_ref(map: &mut LinkedHashMap<String, String>, map2: &LinkedHashMap<String, String>) {
+    map.extend(map2.iter().map(|(k, v)| (k.clone(), v.clone())));
+}
+
+/// Inherited environment extended by job env file and secrets, shared by all job containers
+fn job_env(
+    env_inherited: &LinkedHashMap<String, String>,
     job: &Job,
-    env_inherited: &Option<HashMap<String, String>>,
+) -> LinkedHashMap<String, String> {
+    let mut env = env_inherited.clone();
+    merge_from_ref(&mut env, &job.file_env);
+    if let Some(secrets) = &job.secrets {
+        merge_from_ref(&mut env, secrets);
+    }
+    env
+}
+
+/// Merge inherited and container specific environment, override environment wins over both
+fn container_env(
+    env_inherited: &LinkedHashMap<String, String>,
+    container: &Container,
+    opts: &WorkflowOptions,
+) -> LinkedHashMap<String, String> {
+    let mut env = env_inherited.clone();
+    if let Some(e) = &container.env {
+        merge_from_ref(&mut env, e);
+    }
+    merge_from_ref(&mut env, &opts.env_override);
+    env
+}
+
+/// Poll service healthcheck until it passes or its timeout expires
+fn wait_for_healthy(
+    engine: &dyn Engine,
+    s_name: &str,
+    c_name: &str,
+    check: &Healthcheck,
     opts: &WorkflowOptions,
 ) -> Result<(), String> {
-    let image = &job.container.image;
+    let timeout = check.timeout.unwrap_or(opts.health_timeout);
+    let interval = Duration::from_secs(check.interval.unwrap_or(opts.health_interval));
+    let start = Instant::now();
+    let mut failures = 0;
+    loop {
+        if engine.check_health(c_name, &check.cmd, opts)? {
+            debug!("Service '{s_name}' is healthy");
+            return Ok(());
+        }
+        failures += 1;
+        if check.retries.is_some_and(|r| failures >= r) {
+            return Err(format!(
+                "service '{s_name}' failed health check {failures} times"
+            ));
+        }
+        if start.elapsed() >= Duration::from_secs(timeout) {
+            return Err(format!(
+                "service '{s_name}' failed health check after {timeout}s"
+            ));
+        }
+        sleep(interval);
+    }
+}
 
-    if image.len() == 0 {
-        return Err(format!("No image specified for job {}", name));
+/// Uppercased name with characters other than letters and digits replaced by underscore
+pub fn env_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>()
+        .to_uppercase()
+}
+
+/// Environmental variable name of step input, e.g. `INPUT_DISK_LAYOUT` for `disk-layout`
+fn input_name(key: &str) -> String {
+    format!("INPUT_{}", env_name(key))
+}
+
+/// Environmental variable name of job output, e.g. `IGUANA_PARTITION_ROOT_UUID`
+/// for `root-uuid` output of `partition` job
+fn output_name(job: &str, key: &str) -> String {
+    format!("IGUANA_{}_{}", env_name(job), env_name(key))
+}
+
+/// Environmental variable name of needed job result, e.g. `IGUANA_NEEDS_BUILD_RESULT`
+fn result_name(job: &str) -> String {
+    format!("IGUANA_NEEDS_{}_RESULT", env_name(job))
+}
+
+/// Result of needed job as passed to jobs needing it
+fn result_value(status: JobStatus) -> &'static str {
+    match status {
+        JobStatus::Success => "success",
+        JobStatus::Failed => "failure",
+        JobStatus::Skipped | JobStatus::NoStatus => "skipped",
     }
-    debug!("Running job {}", name);
-    let mut services_ok = true;
+}
 
-    let engine = Podman;
-    // Prepare and run services
-    match &job.services {
-        Some(services) => {
-            for (s_name, s_container) in services.iter() {
-                match engine.prepare_image(&s_container.image, opts.dry_run) {
-                    Ok(()) => (),
-                    Err(e) => {
-                        error!(
-                            "Preparation of service container '{}' failed: {}",
-                            s_name, e
-                        );
-                        services_ok = false;
-                        continue;
+/// Log file receiving output of the job containers and steps
+fn job_log(job: &str, opts: &WorkflowOptions) -> Option<PathBuf> {
+    opts.log_dir.as_ref().map(|d| d.join(format!("{job}.log")))
+}
+
+/// Host file receiving outputs of the job
+fn output_file(job: &str) -> PathBuf {
+    env::temp_dir().join(format!(
+        "iguana-output-{}-{}",
+        process::id(),
+        container_name(job, MAIN_CONTAINER)
+    ))
+}
+
+/// Host directory receiving outputs of steps of the job
+fn step_outputs_dir(job: &str) -> PathBuf {
+    env::temp_dir().join(format!(
+        "iguana-steps-{}-{}",
+        process::id(),
+        container_name(job, MAIN_CONTAINER)
+    ))
+}
+
+/// Read and remove outputs file written by the job, lines in `KEY=VALUE` format
+fn read_outputs(
+    job: &str,
+    opts: &WorkflowOptions,
+) -> Result<LinkedHashMap<String, String>, String> {
+    if opts.dry_run {
+        return Ok(LinkedHashMap::new());
+    }
+    let path = output_file(job);
+    let data = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+    let data = match data {
+        Ok(d) => d,
+        Err(e) => return Err(format!("Unable to read outputs of job '{job}': {e}")),
+    };
+    parse_outputs(&data, &format!("job '{job}'"))
+}
+
+/// Read outputs written by step with id, step which wrote nothing has no outputs
+fn read_step_outputs(
+    job: &str,
+    id: &str,
+    opts: &WorkflowOptions,
+) -> Result<LinkedHashMap<String, String>, String> {
+    if opts.dry_run {
+        return Ok(LinkedHashMap::new());
+    }
+    match fs::read_to_string(step_outputs_dir(job).join(id)) {
+        Ok(data) => parse_outputs(&data, &format!("step '{id}'")),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(LinkedHashMap::new()),
+        Err(e) => Err(format!("Unable to read outputs of step '{id}': {e}")),
+    }
+}
+
+/// Parse outputs in `KEY=VALUE` lines, empty lines are ignored
+fn parse_outputs(data: &str, what: &str) -> Result<LinkedHashMap<String, String>, String> {
+    let mut outputs = LinkedHashMap::new();
+    for (i, line) in data.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match line.split_once('=') {
+            Some((k, v)) if !k.trim().is_empty() => {
+                outputs.insert(k.trim().to_owned(), v.to_owned());
+            }
+            _ => {
+                return Err(format!(
+                    "Output of {what} on line {} is not in KEY=VALUE format",
+                    i + 1
+                ))
+            }
+        }
+    }
+    Ok(outputs)
+}
+
+/// Environment of the job extended by results and outputs of jobs it needs
+///
+/// Needed jobs which were skipped or failed have no outputs.
+fn needs_env(
+    env: &LinkedHashMap<String, String>,
+    job: &Job,
+    jobs_status: &HashMap<String, JobResult>,
+    outputs: &HashMap<String, LinkedHashMap<String, String>>,
+) -> LinkedHashMap<String, String> {
+    let mut env = env.clone();
+    for need in job.needed_jobs() {
+        if let Some(result) = jobs_status.get(need) {
+            env.insert(result_name(need), result_value(result.status).to_owned());
+        }
+        if let Some(job_outputs) = outputs.get(need) {
+            env.extend(
+                job_outputs
+                    .iter()
+                    .map(|(k, v)| (output_name(need, k), v.clone())),
+            );
+        }
+    }
+    env
+}
+
+/// Run image or local action referenced by step `uses` as the step implementation
+///
+/// Action container shares volumes of the job container and gets job environment,
+/// step environment and step inputs. Local action directory is mounted into container
+/// of the job image and its `entrypoint` executable is run.
+#[allow(clippy::too_many_arguments)]
+fn run_action(
+    engine: &dyn Engine,
+    name: &str,
+    a_name: &str,
+    job: &Job,
+    image: &str,
+    step: &Step,
+    job_env: &LinkedHashMap<String, String>,
+    timeout: Option<Duration>,
+    opts: &WorkflowOptions,
+) -> Result<Option<i32>, WorkflowError> {
+    let mut container = Container {
+        volumes: job.container.volumes.clone(),
+        working_directory: step.working_directory.clone(),
+        ..Default::default()
+    };
+    let image = match step.local_action() {
+        Some(dir) => {
+            let entrypoint = opts.resolve_path(dir).join("entrypoint");
+            if !entrypoint.is_file() {
+                return Err(WorkflowError::Validation(format!(
+                    "local action {dir} has no entrypoint file {}",
+                    entrypoint.display()
+                )));
+            }
+            container
+                .volumes
+                .get_or_insert_with(Vec::new)
+                .push(format!("{dir}:{ACTION_TARGET}:ro"));
+            container.entrypoint = Some(format!("{ACTION_TARGET}/entrypoint"));
+            // Job image is prepared and cleaned up with the job
+            image
+        }
+        None => {
+            // Called only for steps with uses
+            let uses = step.uses.as_deref().unwrap_or_default();
+            engine.prepare_image(uses, opts.effective_pull_policy(&container), opts)?;
+            uses
+        }
+    };
+    container.image = image.to_owned();
+
+    let mut env = job_env.clone();
+    if let Some(e) = &step.env {
+        merge_from_ref(&mut env, e);
+    }
+    for (k, v) in step.with.iter().flatten() {
+        env.insert(input_name(k), v.to_owned());
+    }
+    merge_from_ref(&mut env, &opts.env_override);
+
+    let c_name = container_name(name, a_name);
+    let log = job_log(name, opts);
+    let step_outputs = step_outputs_dir(name);
+    let run = ContainerRun {
+        name: &c_name,
+        image,
+        container: &container,
+        detach: false,
+        env,
+        timeout,
+        keep: false,
+        shell: None,
+        idle: false,
+        output: None,
+        step_outputs: step.id.as_ref().map(|_| step_outputs.as_path()),
+        log: log.as_deref(),
+        prefix: &format!("{name}/{a_name}"),
+    };
+    // Step image is released with images of the job, it may be used by running containers
+    engine.run_container(run, opts)
+}
+
+/// Step with references to outputs of earlier steps expanded in its environment and inputs
+///
+/// Step with id gets path of file receiving its own outputs in `IGUANA_STEP_OUTPUT`.
+fn resolve_step(
+    step: &Step,
+    step_outputs: &HashMap<String, Option<LinkedHashMap<String, String>>>,
+) -> Result<Step, String> {
+    let mut step = step.clone();
+    for (_, value) in step.env.iter_mut().chain(step.with.iter_mut()).flatten() {
+        *value = expand_step_outputs(value, step_outputs)?;
+    }
+    if let Some(id) = &step.id {
+        step.env.get_or_insert_with(LinkedHashMap::new).insert(
+            "IGUANA_STEP_OUTPUT".to_owned(),
+            format!("{STEP_OUTPUTS_TARGET}/{id}"),
+        );
+    }
+    Ok(step)
+}
+
+/// Run pre steps, steps and post steps of the job one by one in its running main container
+///
+/// Steps after a failed step are skipped unless their condition checks for failure,
+/// failures of steps with `continue_on_error` are ignored. Post steps run regardless
+/// of failures unless their condition says otherwise.
+/// Job timeout is shared by pre steps and steps, post steps get the same timeout again.
+#[allow(clippy::too_many_arguments)]
+fn run_steps(
+    engine: &dyn Engine,
+    name: &str,
+    job: &Job,
+    image: &str,
+    c_name: &str,
+    env_inherited: &LinkedHashMap<String, String>,
+    opts: &WorkflowOptions,
+    observer: &dyn RunObserver,
+) -> Result<Option<i32>, WorkflowError> {
+    let job_deadline = || job.timeout.map(|t| Instant::now() + Duration::from_secs(t));
+    let mut deadline = job_deadline();
+    let mut job_env = env_inherited.clone();
+    if let Some(e) = &job.container.env {
+        merge_from_ref(&mut job_env, e);
+    }
+    let log = job_log(name, opts);
+    let mut exit_code = None;
+    let mut failed = None;
+    let mut step_outputs = HashMap::new();
+    let pre = job.pre.iter().flatten().map(|s| (s, false));
+    let steps = job.steps.iter().flatten().map(|s| (s, false));
+    let post = job.post.iter().flatten().map(|s| (s, true));
+    let mut in_post = false;
+    for (i, (step, post)) in pre.chain(steps).chain(post).enumerate() {
+        if post && !in_post {
+            in_post = true;
+            deadline = job_deadline();
+        }
+        let s_name = step.display_name(i);
+        let run = match &step.condition {
+            Some(condition) => {
+                let mut env = job_env.clone();
+                if let Some(e) = &step.env {
+                    merge_from_ref(&mut env, e);
+                }
+                condition.evaluate(&Context {
+                    success: failed.is_none(),
+                    failure: failed.is_some(),
+                    env: &env,
+                })
+            }
+            None => post || failed.is_none(),
+        };
+        if !run {
+            debug!("Step '{s_name}' of job '{name}' skipped");
+            observer.on_step_finish(name, &s_name, &StepStatus::Skipped);
+            if let Some(id) = &step.id {
+                step_outputs.insert(id.to_owned(), None);
+            }
+            continue;
+        }
+        observer.on_step_start(name, &s_name);
+        let timeout = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        let resolved = resolve_step(step, &step_outputs);
+        let result = match &resolved {
+            Err(e) => Err(WorkflowError::ContainerRun(e.to_owned())),
+            Ok(step) => match (&step.uses, &step.run) {
+                (Some(_), _) => {
+                    let a_name = format!("step{}", i + 1);
+                    run_action(
+                        engine, name, &a_name, job, image, step, &job_env, timeout, opts,
+                    )
+                }
+                (None, Some(run)) => {
+                    let mut env = step.env.clone().unwrap_or_default();
+                    merge_from_ref(&mut env, &opts.env_override);
+                    let workdir = step
+                        .working_directory
+                        .as_deref()
+                        .or(job.container.working_directory.as_deref());
+                    let shell = step.shell.as_deref().unwrap_or(DEFAULT_SHELL);
+                    let dir_exists = |dir| engine.dir_exists(c_name, dir, opts);
+                    let shell_exists = || engine.shell_exists(c_name, shell, opts);
+                    match workdir {
+                        Some(dir) if !dir_exists(dir).map_err(WorkflowError::ContainerRun)? => {
+                            Err(WorkflowError::ContainerRun(format!(
+                                "working directory {dir} does not exist in container"
+                            )))
+                        }
+                        _ if step.shell.is_some()
+                            && !shell_exists().map_err(WorkflowError::ContainerRun)? =>
+                        {
+                            Err(WorkflowError::ContainerRun(format!(
+                                "shell {shell} can not be run in container"
+                            )))
+                        }
+                        _ => {
+                            let exec = ContainerExec {
+                                cmd: run,
+                                shell,
+                                input: step.input.as_deref(),
+                                env: &env,
+                                workdir,
+                                log: log.as_deref(),
+                                timeout,
+                                prefix: &format!("{name}/{MAIN_CONTAINER}"),
+                            };
+                            engine.exec_in_container(c_name, exec, opts)
+                        }
                     }
                 }
-                let mut env: HashMap<String, String> = HashMap::new();
-                if env_inherited.is_some() {
-                    merge_from_ref(&mut env, env_inherited.as_ref().unwrap());
+                // Rejected when loading the workflow
+                (None, None) => Ok(None),
+            },
+        };
+        let result = match (result, &step.id) {
+            (Ok(code), Some(id)) => match read_step_outputs(name, id, opts) {
+                Ok(outputs) => {
+                    step_outputs.insert(id.to_owned(), Some(outputs));
+                    Ok(code)
                 }
-                if s_container.env.is_some() {
-                    merge_from_ref(&mut env, s_container.env.as_ref().unwrap());
+                Err(e) => {
+                    step_outputs.insert(id.to_owned(), None);
+                    Err(WorkflowError::ContainerRun(e))
                 }
-                match engine.run_container(s_container, true, env, opts) {
-                    Ok(()) => debug!("Service '{}' started", s_name),
-                    Err(e) => {
-                        error!("Service container '{}' start failed: {}", s_name, e);
-                        services_ok = false;
-                    }
+            },
+            (Err(e), Some(id)) => {
+                step_outputs.insert(id.to_owned(), None);
+                Err(e)
+            }
+            (result, None) => result,
+        };
+        match result {
+            Ok(code) => {
+                observer.on_step_finish(name, &s_name, &StepStatus::Success);
+                if !post {
+                    exit_code = code;
+                }
+            }
+            Err(e) => {
+                observer.on_step_finish(name, &s_name, &StepStatus::Failed);
+                let e = e.context(&format!("Job '{name}' step '{s_name}' failed"));
+                if step.continue_on_error {
+                    warn!("{e}, continuing because of continue_on_error");
+                } else {
+                    failed.get_or_insert(e);
+                }
+            }
+        }
+    }
+    match failed {
+        Some(e) => Err(e),
+        None => Ok(exit_code),
+    }
+}
+
+/// Check job can be run at all, such failures are not retried
+fn check_job(
+    name: &str,
+    job: &Job,
+    committed: &HashMap<String, String>,
+) -> Result<(), WorkflowError> {
+    if job.container.detach && job.has_steps() {
+        return Err(WorkflowError::Validation(format!(
+            "Job '{name}' runs detached, it can not have steps"
+        )));
+    }
+    let image = resolve_image(&job.container, committed).map_err(WorkflowError::Validation)?;
+    if image.is_empty() {
+        return Err(WorkflowError::Validation(format!(
+            "No image specified for job {name}"
+        )));
+    }
+    Ok(())
+}
+
+/// Run job, failed runs are repeated as configured by its retry
+#[allow(clippy::too_many_arguments)]
+fn run_job(
+    engine: &dyn Engine,
+    name: &String,
+    job: &Job,
+    env_inherited: &LinkedHashMap<String, String>,
+    committed: &HashMap<String, String>,
+    keep: bool,
+    opts: &WorkflowOptions,
+    observer: &dyn RunObserver,
+) -> Result<Option<i32>, WorkflowError> {
+    check_job(name, job, committed)?;
+    if job.container.command.is_some() && job.has_steps() {
+        warn!("Job '{name}' has steps, command of its container is ignored");
+    }
+    let env_inherited = &job_env(env_inherited, job);
+    let (attempts, delay) = match &job.retry {
+        Some(r) => (r.attempts.max(1), Duration::from_secs(r.delay)),
+        None => (1, Duration::ZERO),
+    };
+    // Log is appended by all attempts and steps of the job
+    if let Some(log) = job_log(name, opts).filter(|_| !opts.dry_run) {
+        if let Err(e) = fs::write(&log, "") {
+            return Err(WorkflowError::Io(format!(
+                "Unable to create log file {}: {e}",
+                log.display()
+            )));
+        }
+    }
+    let mut attempt = 1;
+    loop {
+        match do_job(
+            engine,
+            name,
+            job,
+            env_inherited,
+            committed,
+            keep,
+            opts,
+            observer,
+        ) {
+            // Broken workflow or missing image would fail the same way again
+            Err(e) if attempt < attempts && !e.is_permanent() => {
+                attempt += 1;
+                warn!("{e}, retrying, attempt {attempt} of {attempts}");
+                // Leftovers of failed attempt, containers are replaced by the next one
+                if let Err(e) = clean_job(engine, name, job, opts) {
+                    error!("Failed to clean job {name}: {e}");
                 }
+                sleep(delay);
             }
+            result => return result,
+        }
+    }
+}
+
+/// Prepare image of service container, start it and wait until it is healthy
+fn start_service(
+    engine: &dyn Engine,
+    name: &str,
+    s_name: &str,
+    s_container: &Container,
+    env_inherited: &LinkedHashMap<String, String>,
+    committed: &HashMap<String, String>,
+    opts: &WorkflowOptions,
+) -> Result<(), String> {
+    let s_image = resolve_image(s_container, committed)
+        .map_err(|e| format!("Service container '{s_name}' has no image: {e}"))?;
+    if s_container.from_job.is_none() {
+        engine
+            .prepare_image(s_image, opts.effective_pull_policy(s_container), opts)
+            .map_err(|e| format!("Preparation of service container '{s_name}' failed: {e}"))?;
+    }
+    let env = container_env(env_inherited, s_container, opts);
+    let c_name = container_name(name, s_name);
+    let run = ContainerRun {
+        name: &c_name,
+        image: s_image,
+        container: s_container,
+        detach: true,
+        env,
+        timeout: None,
+        keep: false,
+        shell: None,
+        idle: false,
+        output: None,
+        step_outputs: None,
+        log: None,
+        prefix: &format!("{name}/{s_name}"),
+    };
+    engine
+        .run_container(run, opts)
+        .map_err(|e| format!("Service container '{s_name}' start failed: {e}"))?;
+    debug!("Service '{s_name}' started");
+    if let Some(log_dir) = &opts.log_dir {
+        let path = log_dir.join(format!("{name}.{s_name}.log"));
+        if let Err(e) = engine.follow_logs(&c_name, &path, opts) {
+            warn!("Unable to collect logs of service '{s_name}': {e}");
         }
-        None => {}
     }
+    if let Some(check) = &s_container.healthcheck {
+        wait_for_healthy(engine, s_name, &c_name, check, opts)?;
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn do_job(
+    engine: &dyn Engine,
+    name: &String,
+    job: &Job,
+    env_inherited: &LinkedHashMap<String, String>,
+    committed: &HashMap<String, String>,
+    keep: bool,
+    opts: &WorkflowOptions,
+    observer: &dyn RunObserver,
+) -> Result<Option<i32>, WorkflowError> {
+    let image = resolve_image(&job.container, committed).map_err(WorkflowError::Validation)?;
+    let has_steps = job.has_steps();
+    debug!("Running job {}", name);
+
+    // Services are independent of each other, they are pulled and started in parallel
+    let services_ok = thread::scope(|scope| {
+        let handles: Vec<_> = job
+            .services
+            .iter()
+            .flatten()
+            .map(|(s_name, s_container)| {
+                let handle = scope.spawn(move || {
+                    start_service(
+                        engine,
+                        name,
+                        s_name,
+                        s_container,
+                        env_inherited,
+                        committed,
+                        opts,
+                    )
+                });
+                (s_container.optional, handle)
+            })
+            .collect();
+        // All services are waited for, so none is left starting when the job fails
+        handles
+            .into_iter()
+            .map(|(optional, h)| (optional, h.join().expect("Service start panicked")))
+            .fold(true, |ok, (optional, started)| match started {
+                Ok(()) => ok,
+                Err(e) if optional => {
+                    warn!("{e}, continuing because the service is optional");
+                    ok
+                }
+                Err(e) => {
+                    error!("{e}");
+                    false
+                }
+            })
+    });
 
     if !services_ok {
-        return Err(format!("Service container for job '{}' failed", name));
+        return Err(WorkflowError::ContainerRun(format!(
+            "Service container for job '{name}' failed"
+        )));
     }
 
-    // Start main job
-    match engine.prepare_image(image, opts.dry_run) {
+    // Start main job, images committed from other jobs are already local
+    let prepared = match job.container.from_job {
+        Some(_) => Ok(()),
+        None => engine.prepare_image(image, opts.effective_pull_policy(&job.container), opts),
+    };
+    match prepared {
         Ok(()) => (),
-        Err(e) => return Err(format!("Preparation of container '{}' failed: {}", name, e)),
+        Err(e) => {
+            return Err(e.context(&format!(
+                "Preparation of container '{MAIN_CONTAINER}' for job '{name}' failed"
+            )))
+        }
     }
-    // Merge inherited and job specific environment
-    let mut env: HashMap<String, String> = HashMap::new();
-    if env_inherited.is_some() {
-        let e = env_inherited.as_ref().unwrap();
-        merge_from_ref(&mut env, e);
+    let log = job_log(name, opts);
+    // Outputs of previous attempt are discarded
+    let output = output_file(name);
+    if !opts.dry_run {
+        if let Err(e) = fs::write(&output, "") {
+            return Err(WorkflowError::Io(format!(
+                "Unable to create output file of job '{name}': {e}"
+            )));
+        }
     }
-    if job.container.env.is_some() {
-        let e = job.container.env.as_ref().unwrap();
-        merge_from_ref(&mut env, e);
+    // Step outputs of previous attempt are discarded as well
+    let step_outputs = step_outputs_dir(name);
+    let has_step_outputs = job.all_steps().any(|s| s.id.is_some());
+    if has_step_outputs && !opts.dry_run {
+        let _ = fs::remove_dir_all(&step_outputs);
+        if let Err(e) = fs::create_dir(&step_outputs) {
+            return Err(WorkflowError::Io(format!(
+                "Unable to create step outputs directory of job '{name}': {e}"
+            )));
+        }
     }
-    match engine.run_container(&job.container, false, env, opts) {
-        Ok(()) => debug!("Job container '{}' started", image),
-        Err(e) => {
-            return Err(format!("Job container '{}' start failed: {}", image, e));
+    let env = container_env(&job_env(env_inherited, job), &job.container, opts);
+    let c_name = container_name(name, MAIN_CONTAINER);
+    let run = ContainerRun {
+        name: &c_name,
+        image,
+        container: &job.container,
+        detach: job.container.detach,
+        env,
+        timeout: job.timeout.map(Duration::from_secs),
+        keep,
+        shell: None,
+        idle: has_steps,
+        output: Some(&output),
+        step_outputs: Some(step_outputs.as_path()).filter(|_| has_step_outputs),
+        log: log
+            .as_deref()
+            .filter(|_| !has_steps && !job.container.detach),
+        prefix: &format!("{name}/{MAIN_CONTAINER}"),
+    };
+    if has_steps {
+        let result = match engine.run_container(run, opts) {
+            Ok(_) => {
+                let result = run_steps(
+                    engine,
+                    name,
+                    job,
+                    image,
+                    &c_name,
+                    env_inherited,
+                    opts,
+                    observer,
+                );
+                // Idle container would not react to stop signal
+                if let Err(e) = engine.kill_container(&c_name, opts) {
+                    error!("Stopping of job '{name}' container failed: {e}");
+                }
+                result
+            }
+            Err(e) => Err(e.context(&format!("Job '{name}' container '{MAIN_CONTAINER}' failed"))),
+        };
+        if has_step_outputs && !opts.dry_run {
+            let _ = fs::remove_dir_all(&step_outputs);
         }
+        return result;
+    }
+    let result = engine.run_container(run, opts);
+    if let (Ok(_), Some(log), true) = (&result, &log, job.container.detach) {
+        if let Err(e) = engine.follow_logs(&c_name, log, opts) {
+            warn!("Unable to collect logs of job '{name}': {e}");
+        }
+    }
+    match result {
+        Ok(exit_code) => {
+            debug!("Job '{name}' container '{MAIN_CONTAINER}' finished");
+            Ok(exit_code)
+        }
+        Err(e) => Err(e.context(&format!("Job '{name}' container '{MAIN_CONTAINER}' failed"))),
     }
-
-    Ok(())
 }
 
-fn clean_job(job: &Job, opts: &WorkflowOptions) -> Result<(), String> {
+/// Prepare main container of the job as for the real run, but start interactive shell in it
+///
+/// Services are not started.
+pub fn debug_shell(
+    name: &str,
+    job: &Job,
+    env_inherited: &LinkedHashMap<String, String>,
+    opts: &WorkflowOptions,
+) -> Result<(), WorkflowError> {
+    if job.container.from_job.is_some() {
+        return Err(WorkflowError::Validation(format!(
+            "Debug shell is not supported for job '{name}', its container is based on another job"
+        )));
+    }
+    if job.container.image.is_empty() {
+        return Err(WorkflowError::Validation(format!(
+            "No image specified for job {name}"
+        )));
+    }
+    if job.services.is_some() {
+        warn!("Services of job '{name}' are not started in debug shell");
+    }
+
     let engine = Podman;
+    engine.prepare_image(
+        &job.container.image,
+        opts.effective_pull_policy(&job.container),
+        opts,
+    )?;
+    let env = container_env(env_inherited, &job.container, opts);
+    let c_name = container_name(name, MAIN_CONTAINER);
+    let run = ContainerRun {
+        name: &c_name,
+        image: &job.container.image,
+        container: &job.container,
+        detach: false,
+        env,
+        timeout: None,
+        keep: false,
+        shell: Some(DEBUG_SHELL),
+        idle: false,
+        output: None,
+        step_outputs: None,
+        log: None,
+        prefix: &format!("{name}/{MAIN_CONTAINER}"),
+    };
+    let result = engine.run_container(run, opts);
+    if let Err(e) = clean_job(&engine, name, job, opts) {
+        error!("Failed to clean job {name}: {e}");
+    }
+    if let Err(e) = engine.clean_image(&job.container.image, opts) {
+        error!("Image {} cleanup failed: {e}", job.container.image);
+    }
+    match result {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.context(&format!("Debug shell of job '{name}' failed"))),
+    }
+}
+
+fn clean_job(
+    engine: &dyn Engine,
+    name: &str,
+    job: &Job,
+    opts: &WorkflowOptions,
+) -> Result<(), String> {
     // Collect volumes through cleanup so we can removed them at the end
     let mut volumes = HashSet::new();
     // Stop service containers
-    match &job.services {
-        Some(services) => {
-            for (s_name, s_container) in services.iter() {
-                match engine.stop_container(&s_container.image, opts) {
-                    Ok(()) => debug!("Service container '{s_name}' stopped"),
-                    Err(e) => {
-                        error!("Stopping of service container '{s_name}' failed: {e}");
-                    }
+    if let Some(services) = &job.services {
+        for (s_name, s_container) in services.iter() {
+            let c_name = container_name(name, s_name);
+            match engine.stop_container(&c_name, opts) {
+                Ok(()) => debug!("Service container '{s_name}' stopped"),
+                Err(e) => {
+                    error!("Stopping of service container '{s_name}' failed: {e}");
                 }
-
-                match engine.clean_image(&s_container.image, opts) {
-                    Ok(()) => debug!("Service '{s_name}' image cleaned"),
-                    Err(e) => {
-                        error!("Service container '{s_name}' cleanup failed: {e}");
-                    }
+            }
+            // Restarted containers are not run with --rm
+            if s_container.restarts() && opts.cleanup {
+                if let Err(e) = engine.remove_container(&c_name, opts) {
+                    error!("Removal of service container '{s_name}' failed: {e}");
                 }
+            }
 
-                if s_container.volumes.is_some() {
-                    for v in s_container.volumes.as_ref().unwrap() {
-                        let src = v.split(":").take(1).collect::<Vec<_>>()[0];
-                        volumes.insert(src);
-                    }
-                }
+            volumes.extend(s_container.named_volumes());
+        }
+    }
+
+    volumes.extend(job.container.named_volumes());
+
+    if !volumes.is_empty() {
+        return engine.clean_volumes(&volumes, opts);
+    }
+    Ok(())
+}
+
+/// Job artifacts are collected only when result directory is set
+fn collects_artifacts(job: &Job, opts: &WorkflowOptions) -> bool {
+    opts.result_dir.is_some() && job.artifacts.is_some()
+}
+
+/// Copy job artifacts from its main container into `<result dir>/<job>/`
+fn collect_artifacts(engine: &dyn Engine, name: &str, job: &Job, opts: &WorkflowOptions) {
+    let (result_dir, artifacts) = match (&opts.result_dir, &job.artifacts) {
+        (Some(d), Some(a)) => (d, a),
+        _ => return,
+    };
+    let dest = result_dir.join(name);
+    if !opts.dry_run {
+        if let Err(e) = fs::create_dir_all(&dest) {
+            error!("Unable to create result directory {}: {e}", dest.display());
+            return;
+        }
+    }
+    let c_name = container_name(name, MAIN_CONTAINER);
+    for artifact in artifacts.iter() {
+        match engine.copy_from_container(&c_name, artifact, &dest, opts) {
+            Ok(()) => debug!("Artifact {artifact} of job '{name}' collected"),
+            Err(e) => warn!("Unable to collect artifact {artifact} of job '{name}': {e}"),
+        }
+    }
+}
+
+/// Collect artifacts of finished job and commit its main container into image
+/// for jobs using it as their base
+///
+/// Container was kept after exit so it could be copied from and committed, remove it afterwards.
+/// Returns the committed image.
+fn release_job(
+    engine: &dyn Engine,
+    name: &str,
+    job: &Job,
+    succeeded: bool,
+    commit: bool,
+    opts: &WorkflowOptions,
+) -> Option<String> {
+    collect_artifacts(engine, name, job, opts);
+    let c_name = container_name(name, MAIN_CONTAINER);
+    let mut committed = None;
+    if succeeded && commit {
+        let image = committed_image(name);
+        match engine.commit_container(&c_name, &image, opts) {
+            Ok(()) => {
+                debug!("Job '{name}' committed as {image}");
+                committed = Some(image);
             }
+            Err(e) => error!("Commit of job '{name}' container failed: {e}"),
+        }
+    }
+    if opts.cleanup {
+        if let Err(e) = engine.remove_container(&c_name, opts) {
+            error!("Removal of job '{name}' container failed: {e}");
         }
-        None => {}
     }
+    committed
+}
 
-    if job.container.volumes.is_some() {
-        for v in job.container.volumes.as_ref().unwrap() {
-            let src = v.split(":").take(1).collect::<Vec<_>>()[0];
-            volumes.insert(src);
+/// Unique images used by jobs, images committed during the run are not included
+fn workflow_images(jobs: &LinkedHashMap<String, Job>) -> Vec<&str> {
+    let mut images = Vec::new();
+    let mut seen = HashSet::new();
+    for job in jobs.values() {
+        for (_, container) in job.containers() {
+            if container.from_job.is_none() && seen.insert(container.image.as_str()) {
+                images.push(container.image.as_str());
+            }
         }
     }
+    images
+}
 
-    if !volumes.is_empty() {
-        match engine.clean_volumes(&volumes, opts) {
-            Ok(()) => debug!("Container volumes removed"),
+/// Resolve all images used by jobs to digests in parallel and print them
+///
+/// Reports every unresolvable image at once
+pub fn validate_images(
+    jobs: &LinkedHashMap<String, Job>,
+    opts: &WorkflowOptions,
+) -> Result<(), WorkflowError> {
+    let images = workflow_images(jobs);
+    let resolved: Vec<(&str, Result<String, String>)> = thread::scope(|scope| {
+        let handles: Vec<_> = images
+            .iter()
+            .map(|image| scope.spawn(move || (*image, Podman.image_digest(image, opts))))
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("Image digest resolution panicked"))
+            .collect()
+    });
+
+    let mut unresolved = Vec::new();
+    for (image, digest) in resolved {
+        match digest {
+            Ok(d) => println!("{image} {d}"),
             Err(e) => {
-                error!("Container volume cleanup failed: {e}");
+                error!("Unable to resolve image {image}: {e}");
+                unresolved.push(image);
             }
         }
     }
+    if !unresolved.is_empty() {
+        return Err(WorkflowError::ImagePull(format!(
+            "Images could not be resolved: {}",
+            unresolved.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// Verify all images used by jobs are present in local storage
+///
+/// Reports every missing image at once instead of failing on the first job using it
+pub fn check_local_images(
+    jobs: &LinkedHashMap<String, Job>,
+    opts: &WorkflowOptions,
+) -> Result<(), WorkflowError> {
+    let engine = Podman;
+    let mut missing = Vec::new();
+    for image in workflow_images(jobs) {
+        if !engine
+            .image_exists(image, opts)
+            .map_err(WorkflowError::ImagePull)?
+        {
+            missing.push(image);
+        }
+    }
 
-    // Clean images
-    return engine.clean_image(&job.container.image, opts);
+    if !missing.is_empty() {
+        return Err(WorkflowError::ImagePull(format!(
+            "Images not available locally: {}",
+            missing.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// Reason why job can not run, when its needs can no longer be satisfied
+///
+/// Only successful jobs satisfy needs, jobs not in the workflow are not checked.
+fn unsatisfied_needs(
+    name: &str,
+    needs: &Needs,
+    jobs_status: &HashMap<String, JobResult>,
+) -> Option<String> {
+    let mut unsatisfied = Vec::new();
+    for need in needs.jobs() {
+        match jobs_status.get(need).map(|r| &r.status) {
+            None => {
+                warn!(
+                    "Job {name} requires {need} which is not part of the workflow! Skipping check!"
+                )
+            }
+            Some(JobStatus::Success) => {}
+            // Failed, skipped or never run dependency, skips propagate down the graph
+            Some(_) => unsatisfied.push(need.as_str()),
+        }
+    }
+    match needs {
+        Needs::All(_) => unsatisfied
+            .first()
+            .map(|need| format!("dependency {need} did not succeed")),
+        Needs::AnyOf { any_of } if !any_of.is_empty() && unsatisfied.len() == any_of.len() => Some(
+            format!("none of dependencies {} succeeded", unsatisfied.join(", ")),
+        ),
+        Needs::AnyOf { .. } => None,
+    }
+}
+
+/// Whether any job needed by the job failed
+fn needs_failed(job: &Job, jobs_status: &HashMap<String, JobResult>) -> bool {
+    job.needed_jobs().iter().any(|need| {
+        jobs_status
+            .get(need)
+            .is_some_and(|r| r.status == JobStatus::Failed)
+    })
+}
+
+/// Report of job worker thread about finished job
+struct Finished<'a> {
+    name: &'a str,
+    result: Result<Option<i32>, WorkflowError>,
+    duration: Duration,
+    steps: Vec<(String, StepStatus)>,
+    /// Image committed from the job main container
+    committed: Option<String>,
+    /// Outputs written by successful job
+    outputs: LinkedHashMap<String, String>,
 }
 
-/// Analyze "jobs" key of workflow and execute jobs in order
+/// Images pulled for job containers and steps, committed images are cleaned separately
+fn job_images(job: &Job) -> impl Iterator<Item = &str> {
+    let steps = job
+        .all_steps()
+        .filter(|s| s.local_action().is_none())
+        .filter_map(|s| s.uses.as_deref());
+    job.containers()
+        .filter(|(_, c)| c.from_job.is_none())
+        .map(|(_, c)| c.image.as_str())
+        .chain(steps)
+}
+
+/// Remove images of finished job unless they are still used by unfinished jobs
+fn release_images<'a>(
+    engine: &dyn Engine,
+    job: &'a Job,
+    image_users: &mut HashMap<&'a str, usize>,
+    opts: &WorkflowOptions,
+) {
+    for image in job_images(job) {
+        let users = image_users.entry(image).or_default();
+        *users = users.saturating_sub(1);
+        if *users == 0 {
+            if let Err(e) = engine.clean_image(image, opts) {
+                error!("Image {image} cleanup failed: {e}");
+            }
+        }
+    }
+}
+
+/// Analyze "jobs" key of workflow and execute jobs, independent jobs run in parallel
+///
+/// Jobs are started in workflow order as soon as all jobs they depend on finished,
+/// at most `max_parallel` of them at once. After a failure of job without
+/// `continue_on_error` no more jobs are started and running ones are waited for,
+/// unless fail fast is disabled. Then only jobs needing the failed one are skipped.
+/// Jobs with results given in `jobs_status`, from resumed run, are not run again.
+/// Results of all jobs are returned together with the first failure.
 pub fn do_jobs(
+    engine: &dyn Engine,
     jobs: LinkedHashMap<String, Job>,
-    mut jobs_status: HashMap<String, JobStatus>,
-    env: &Option<HashMap<String, String>>,
-    opts: &WorkflowOptions,
-) -> Result<HashMap<String, JobStatus>, String> {
-    // skip if job needs another one which already run and failed
-    for (name, job) in jobs.iter() {
-        jobs_status.insert(name.to_owned(), JobStatus::NoStatus);
-        let mut skip = false;
-        match &job.needs {
-            Some(needs) => {
-                for need in needs.iter() {
-                    if !jobs_status.contains_key(need) {
-                        warn!("Job {name} requires {need} but this was not scheduled yet! Skipping check!");
-                    } else if jobs_status[need] == JobStatus::Failed {
-                        warn!("Skipping job {name} because of failed dependency {need}");
-                        skip = true;
-                        break;
+    mut jobs_status: HashMap<String, JobResult>,
+    env: &LinkedHashMap<String, String>,
+    opts: &WorkflowOptions,
+    observer: &dyn RunObserver,
+) -> (HashMap<String, JobResult>, Result<(), WorkflowError>) {
+    // Jobs whose main container is used as base of other containers
+    let mut commit_jobs = HashSet::new();
+    for job in jobs.values() {
+        commit_jobs.extend(job.container.from_job.as_deref());
+        if let Some(services) = &job.services {
+            commit_jobs.extend(services.values().filter_map(|s| s.from_job.as_deref()));
+        }
+    }
+    let mut committed = HashMap::new();
+    // Jobs whose main container still runs in background
+    let mut detached = Vec::new();
+    let mut failure = None;
+    // Images are shared by jobs, so they are cleaned once the last job using them finished
+    let mut image_users: HashMap<&str, usize> = HashMap::new();
+    for (_, job) in jobs.iter().filter(|(j, _)| !jobs_status.contains_key(*j)) {
+        for image in job_images(job) {
+            *image_users.entry(image).or_default() += 1;
+        }
+    }
+    let mut started_images = HashSet::new();
+    // Outputs of successful jobs passed to jobs which need them
+    let mut outputs: HashMap<String, LinkedHashMap<String, String>> = jobs_status
+        .iter()
+        .map(|(name, r)| (name.to_owned(), r.outputs.clone()))
+        .collect();
+
+    // Jobs with results, which succeeded in resumed run, are not run again
+    let mut pending: Vec<&String> = jobs
+        .keys()
+        .filter(|j| !jobs_status.contains_key(*j))
+        .collect();
+    let mut running = 0;
+    // Progress counts finished jobs, resumed ones included, as jobs may run in parallel
+    let total = jobs.len();
+    let mut done = jobs_status.len();
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+        loop {
+            // Ready jobs are started in workflow order to keep runs reproducible
+            let mut i = 0;
+            // Aborted workflow does not start any more jobs
+            while running < opts.max_parallel && i < pending.len() && !interrupt::aborted() {
+                let name = pending[i];
+                let job = &jobs[name];
+                // After failure only jobs whose condition handles it are started
+                if failure.is_some()
+                    && opts.fail_fast.unwrap_or(true)
+                    && !job.condition.as_ref().is_some_and(|c| c.handles_failure())
+                {
+                    i += 1;
+                    continue;
+                }
+                let waiting = job.run_after().any(|dep| {
+                    jobs.contains_key(dep)
+                        && jobs_status
+                            .get(dep)
+                            .is_none_or(|r| r.status == JobStatus::NoStatus)
+                });
+                if waiting {
+                    i += 1;
+                    continue;
+                }
+                pending.remove(i);
+                jobs_status.insert(name.to_owned(), JobResult::new(JobStatus::NoStatus));
+
+                // skip if job needs can no longer be satisfied or its condition does not hold
+                let unsatisfied = match &job.needs {
+                    Some(needs) => unsatisfied_needs(name, needs, &jobs_status),
+                    None => None,
+                };
+                let env = needs_env(env, job, &jobs_status, &outputs);
+                let skip = match &job.condition {
+                    Some(condition) => {
+                        let context = Context {
+                            success: unsatisfied.is_none(),
+                            failure: needs_failed(job, &jobs_status),
+                            env: &env,
+                        };
+                        (!condition.evaluate(&context))
+                            .then(|| format!("condition '{condition}' is not met"))
                     }
+                    None => unsatisfied,
+                };
+                if let Some(reason) = skip {
+                    warn!("Skipping job {name} because {reason}");
+                    let mut result = JobResult::new(JobStatus::Skipped);
+                    result.error = Some(reason);
+                    jobs_status.insert(name.to_owned(), result);
+                    done += 1;
+                    info!("[{done}/{total}] Job {name} finished with status skipped");
+                    observer.on_job_finish(name, &JobStatus::Skipped);
+                    release_images(engine, job, &mut image_users, opts);
+                    // Skipped job may unblock jobs checked before it
+                    i = 0;
+                    continue;
                 }
+
+                info!("[{done}/{total}] Starting job {name}");
+                observer.on_job_start(name);
+                started_images.extend(job_images(job));
+                let commit = commit_jobs.contains(name.as_str());
+                let keep = commit || collects_artifacts(job, opts);
+                let committed = committed.clone();
+                let tx = tx.clone();
+                scope.spawn(move || {
+                    let start = Instant::now();
+                    let recorder = StepRecorder {
+                        inner: observer,
+                        steps: Mutex::new(Vec::new()),
+                    };
+                    let mut result =
+                        run_job(engine, name, job, &env, &committed, keep, opts, &recorder);
+                    let mut job_outputs = LinkedHashMap::new();
+                    match read_outputs(name, opts) {
+                        Ok(o) => job_outputs = o,
+                        Err(e) if result.is_ok() => result = Err(WorkflowError::ContainerRun(e)),
+                        Err(_) => {}
+                    }
+                    // Detached container still runs, it is released after all jobs
+                    let running_detached = job.container.detach && result.is_ok();
+                    let mut image = None;
+                    if keep && !running_detached {
+                        image = release_job(engine, name, job, result.is_ok(), commit, opts);
+                    }
+                    if !running_detached {
+                        if let Err(e) = clean_job(engine, name, job, opts) {
+                            error!("Failed to clean job {name}: {e}");
+                        }
+                    }
+                    let duration = start.elapsed();
+                    let _ = tx.send(Finished {
+                        name,
+                        result,
+                        duration,
+                        steps: recorder.steps.into_inner().expect("Steps lock poisoned"),
+                        committed: image,
+                        outputs: job_outputs,
+                    });
+                });
+                running += 1;
             }
-            None => {}
-        }
-        if skip {
-            jobs_status.insert(name.to_owned(), JobStatus::Skipped);
-            continue;
-        }
 
-        match do_job(name, job, env, opts) {
-            Ok(()) => {
-                jobs_status.insert(name.to_owned(), JobStatus::Success);
+            if running == 0 {
+                break;
             }
-            Err(e) => {
-                jobs_status.insert(name.to_owned(), JobStatus::Failed);
-                if !job.continue_on_error {
-                    return Err(e);
+            let finished = rx.recv().expect("Job worker exited without reporting");
+            running -= 1;
+            done += 1;
+
+            let name = finished.name;
+            let job = &jobs[name];
+            if let Some(image) = finished.committed {
+                committed.insert(name.to_owned(), image);
+            }
+            let mut result = JobResult::new(JobStatus::Success);
+            result.duration = Some(finished.duration);
+            result.steps = finished.steps;
+            match finished.result {
+                Ok(exit_code) => {
+                    result.outputs.clone_from(&finished.outputs);
+                    outputs.insert(name.to_owned(), finished.outputs);
+                    result.exit_code = exit_code;
+                    jobs_status.insert(name.to_owned(), result);
+                    info!("[{done}/{total}] Job {name} finished with status success");
+                    observer.on_job_finish(name, &JobStatus::Success);
+                }
+                Err(e) => {
+                    result.status = JobStatus::Failed;
+                    result.error = Some(e.to_string());
+                    jobs_status.insert(name.to_owned(), result);
+                    warn!("[{done}/{total}] Job {name} finished with status failed");
+                    observer.on_job_error(name, e.message());
+                    observer.on_job_finish(name, &JobStatus::Failed);
+                    if !job.continue_on_error {
+                        failure.get_or_insert(e);
+                    } else {
+                        warn!("{e}, continuing because of continue_on_error");
+                    }
                 }
             }
+
+            // Detached job keeps running, it is cleaned after all jobs
+            if job.container.detach && jobs_status[name].status == JobStatus::Success {
+                detached.push((name, job));
+            } else {
+                release_images(engine, job, &mut image_users, opts);
+            }
         }
+    });
 
-        match clean_job(job, opts) {
-            Ok(()) => {}
-            Err(e) => {
-                error!("Failed to clean job {name}: {e}");
+    for (name, job) in detached {
+        match engine.stop_container(&container_name(name, MAIN_CONTAINER), opts) {
+            Ok(()) => debug!("Detached job '{name}' stopped"),
+            Err(e) => error!("Stopping of detached job '{name}' failed: {e}"),
+        }
+        if collects_artifacts(job, opts) {
+            release_job(engine, name, job, true, false, opts);
+        }
+        if let Err(e) = clean_job(engine, name, job, opts) {
+            error!("Failed to clean job {name}: {e}");
+        }
+        release_images(engine, job, &mut image_users, opts);
+    }
+
+    // Images of jobs not run after failure may be shared with jobs which ran
+    for (image, users) in image_users.iter() {
+        if *users > 0 && started_images.contains(image) {
+            if let Err(e) = engine.clean_image(image, opts) {
+                error!("Image {image} cleanup failed: {e}");
             }
-        };
+        }
+    }
+    for image in committed.values() {
+        if let Err(e) = engine.clean_image(image, opts) {
+            error!("Failed to clean committed image {image}: {e}");
+        }
+    }
+    let result = match failure {
+        Some(e) => Err(e),
+        None => Ok(()),
+    };
+    (jobs_status, result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workflow::testing::{jobs, options, serial, MockEngine, NoObserver};
+
+    fn run(
+        engine: &MockEngine,
+        workflow: &str,
+        opts: &WorkflowOptions,
+    ) -> HashMap<String, JobResult> {
+        let _serial = serial();
+        let env = LinkedHashMap::new();
+        do_jobs(
+            engine,
+            jobs(workflow),
+            HashMap::new(),
+            &env,
+            opts,
+            &NoObserver,
+        )
+        .0
+    }
+
+    #[test]
+    fn ready_jobs_run_in_file_order() {
+        let workflow = "
+jobs:
+  c:
+    container:
+      image: registry.example.com/base:1
+  a:
+    container:
+      image: registry.example.com/base:1
+  d:
+    needs: [c]
+    container:
+      image: registry.example.com/base:1
+  b:
+    container:
+      image: registry.example.com/base:1
+";
+        let engine = MockEngine::default();
+        run(&engine, workflow, &options());
+        // Job needing another one starts before later jobs once it is ready
+        assert_eq!(
+            engine.runs(),
+            [
+                "iguana-c-main",
+                "iguana-a-main",
+                "iguana-d-main",
+                "iguana-b-main"
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_step_continuing_on_error_does_not_fail_job() {
+        let workflow = "
+jobs:
+  install:
+    container:
+      image: registry.example.com/base:1
+    steps:
+      - name: probe
+        run: probe-disk
+        continue_on_error: true
+      - name: install
+        run: install-system
+";
+        let engine = MockEngine::failing_steps(&["probe-disk"]);
+        let status = run(&engine, workflow, &options());
+        let result = &status["install"];
+        assert!(result.status == JobStatus::Success);
+        let steps: Vec<_> = result
+            .steps
+            .iter()
+            .map(|(name, status)| format!("{name} {status}"))
+            .collect();
+        assert_eq!(steps, ["probe failed", "install success"]);
+        assert!(engine
+            .calls()
+            .contains(&"exec iguana-install-main install-system".to_owned()));
+    }
+
+    #[test]
+    fn skips_propagate_down_needs_chain() {
+        let workflow = "
+jobs:
+  root:
+    container:
+      image: registry.example.com/base:1
+  child:
+    needs: [root]
+    container:
+      image: registry.example.com/base:1
+  grandchild:
+    needs: [child]
+    container:
+      image: registry.example.com/base:1
+";
+        let engine = MockEngine::failing(&["iguana-root-main"]);
+        let mut opts = options();
+        opts.fail_fast = Some(false);
+        let status = run(&engine, workflow, &opts);
+        assert!(status["root"].status == JobStatus::Failed);
+        assert!(status["child"].status == JobStatus::Skipped);
+        assert!(status["grandchild"].status == JobStatus::Skipped);
+        assert_eq!(
+            status["grandchild"].error.as_deref(),
+            Some("dependency child did not succeed")
+        );
+        assert_eq!(engine.runs(), ["iguana-root-main"]);
+    }
+
+    const NEEDS: &str = "
+jobs:
+  probe:
+    container:
+      image: registry.example.com/base:1
+  fetch:
+    container:
+      image: registry.example.com/base:1
+  mirror:
+    needs: [fetch]
+    container:
+      image: registry.example.com/base:1
+  install:
+    needs: NEEDS
+    container:
+      image: registry.example.com/base:1
+";
+
+    /// Results of jobs when given containers fail, `install` needing as given
+    fn run_needs(needs: &str, failing: &[&str]) -> HashMap<String, JobResult> {
+        let engine = MockEngine::failing(failing);
+        let mut opts = options();
+        opts.fail_fast = Some(false);
+        run(&engine, &NEEDS.replace("NEEDS", needs), &opts)
+    }
+
+    #[test]
+    fn any_of_runs_when_one_dependency_succeeded() {
+        let status = run_needs("{any_of: [probe, fetch]}", &["iguana-probe-main"]);
+        assert!(status["probe"].status == JobStatus::Failed);
+        assert!(status["install"].status == JobStatus::Success);
+    }
+
+    #[test]
+    fn any_of_is_skipped_when_no_dependency_succeeded() {
+        let status = run_needs(
+            "{any_of: [probe, mirror]}",
+            &["iguana-probe-main", "iguana-fetch-main"],
+        );
+        assert!(status["mirror"].status == JobStatus::Skipped);
+        assert!(status["install"].status == JobStatus::Skipped);
+        assert_eq!(
+            status["install"].error.as_deref(),
+            Some("none of dependencies probe, mirror succeeded")
+        );
+    }
+
+    #[test]
+    fn list_is_skipped_when_one_dependency_failed() {
+        let status = run_needs("[probe, fetch]", &["iguana-fetch-main"]);
+        assert!(status["probe"].status == JobStatus::Success);
+        assert!(status["install"].status == JobStatus::Skipped);
+        assert_eq!(
+            status["install"].error.as_deref(),
+            Some("dependency fetch did not succeed")
+        );
+    }
+
+    const SERVICES: &str = "
+jobs:
+  install:
+    container:
+      image: registry.example.com/base:1
+    services:
+      db:
+        image: registry.example.com/db:1
+      cache:
+        image: registry.example.com/cache:1
+        optional: OPTIONAL
+";
+
+    #[test]
+    fn failed_optional_service_lets_job_run() {
+        let engine = MockEngine::failing(&["iguana-install-cache"]);
+        let status = run(&engine, &SERVICES.replace("OPTIONAL", "true"), &options());
+        assert!(status["install"].status == JobStatus::Success);
+        assert!(engine.runs().contains(&"iguana-install-main".to_owned()));
+    }
+
+    #[test]
+    fn failed_required_service_fails_job_and_stops_services() {
+        let engine = MockEngine::failing(&["iguana-install-cache"]);
+        let status = run(&engine, &SERVICES.replace("OPTIONAL", "false"), &options());
+        assert!(status["install"].status == JobStatus::Failed);
+        assert!(!engine.runs().contains(&"iguana-install-main".to_owned()));
+        assert!(engine
+            .calls()
+            .contains(&"stop iguana-install-db".to_owned()));
+    }
+
+    #[test]
+    fn local_action_runs_entrypoint_in_job_image() {
+        let workflow = "
+jobs:
+  install:
+    container:
+      image: registry.example.com/base:1
+    steps:
+      - uses: ./actions/partition
+      - uses: ./actions/missing
+";
+        let context = env::temp_dir().join(format!("iguana-test-{}", process::id()));
+        let action = context.join("actions/partition");
+        fs::create_dir_all(&action).unwrap();
+        fs::write(action.join("entrypoint"), "#!/bin/sh\n").unwrap();
+        let mut opts = options();
+        opts.context = Some(context.clone());
+        let engine = MockEngine::default();
+        let status = run(&engine, workflow, &opts);
+        fs::remove_dir_all(&context).unwrap();
+        // Action without entrypoint fails the step, no image is pulled for actions
+        assert!(status["install"].status == JobStatus::Failed);
+        assert_eq!(
+            engine.calls()[..3],
+            [
+                "pull registry.example.com/base:1",
+                "run iguana-install-main",
+                "run iguana-install-step1"
+            ]
+        );
+        assert!(!engine.runs().contains(&"iguana-install-step2".to_owned()));
+    }
+
+    #[test]
+    fn step_images_are_released_with_job() {
+        let workflow = "
+jobs:
+  install:
+    container:
+      image: registry.example.com/base:1
+    steps:
+      - uses: registry.example.com/partition:1
+      - uses: registry.example.com/base:1
+      - uses: registry.example.com/partition:1
+";
+        let engine = MockEngine::default();
+        run(&engine, workflow, &options());
+        // Images are removed once, after the job container and all steps finished
+        let calls = engine.calls();
+        let cleaned: Vec<_> = calls.iter().filter(|c| c.starts_with("clean ")).collect();
+        assert_eq!(
+            cleaned,
+            [
+                "clean registry.example.com/base:1",
+                "clean registry.example.com/partition:1"
+            ]
+        );
+        let last_run = calls.iter().rposition(|c| c.starts_with("run ")).unwrap();
+        let first_clean = calls.iter().position(|c| c.starts_with("clean ")).unwrap();
+        assert!(last_run < first_clean);
     }
-    Ok(jobs_status)
 }
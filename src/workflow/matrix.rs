@@ -0,0 +1,163 @@
+/// Expansion of jobs with matrix strategy into job per combination of matrix values
+use linked_hash_map::LinkedHashMap;
+
+use std::collections::HashMap;
+
+use crate::workflow::{Container, Job, Needs};
+
+/// Values of matrix variables of single expanded job
+type Combination = LinkedHashMap<String, String>;
+
+/// All combinations of matrix values, first variable changes slowest
+fn combinations(matrix: &LinkedHashMap<String, Vec<String>>) -> Vec<Combination> {
+    let mut combinations = vec![Combination::new()];
+    for (key, values) in matrix.iter() {
+        combinations = combinations
+            .into_iter()
+            .flat_map(|c| {
+                values.iter().map(move |v| {
+                    let mut c = c.clone();
+                    c.insert(key.to_owned(), v.to_owned());
+                    c
+                })
+            })
+            .collect();
+    }
+    combinations
+}
+
+/// Replace `${{ matrix.<key> }}` references with values of the combination
+fn substitute(value: &str, combination: &Combination) -> Result<String, String> {
+    let mut substituted = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${{") {
+        substituted.push_str(&rest[..start]);
+        let end = match rest[start..].find("}}") {
+            Some(e) => start + e,
+            None => return Err(format!("Unterminated matrix reference in '{value}'")),
+        };
+        let reference = rest[start + 3..end].trim();
+        let key = match reference.strip_prefix("matrix.") {
+            Some(k) => k,
+            None => return Err(format!("Unknown reference '{reference}' in '{value}'")),
+        };
+        match combination.get(key) {
+            Some(v) => substituted.push_str(v),
+            None => return Err(format!("Matrix has no variable '{key}'")),
+        }
+        rest = &rest[end + 2..];
+    }
+    substituted.push_str(rest);
+    Ok(substituted)
+}
+
+fn substitute_env(
+    env: &mut Option<LinkedHashMap<String, String>>,
+    combination: &Combination,
+) -> Result<(), String> {
+    for (_, value) in env.iter_mut().flat_map(|e| e.iter_mut()) {
+        *value = substitute(value, combination)?;
+    }
+    Ok(())
+}
+
+fn substitute_container(
+    container: &mut Container,
+    combination: &Combination,
+) -> Result<(), String> {
+    container.image = substitute(&container.image, combination)?;
+    substitute_env(&mut container.env, combination)
+}
+
+/// Job with matrix references replaced by values of the combination
+fn expand_job(job: &Job, combination: &Combination) -> Result<Job, String> {
+    let mut job = job.clone();
+    job.matrix = None;
+    substitute_container(&mut job.container, combination)?;
+    for service in job.services.iter_mut().flat_map(|s| s.values_mut()) {
+        substitute_container(service, combination)?;
+    }
+    for step in job.all_steps_mut() {
+        if let Some(run) = &step.run {
+            step.run = Some(substitute(run, combination)?);
+        }
+        substitute_env(&mut step.env, combination)?;
+        substitute_env(&mut step.with, combination)?;
+    }
+    Ok(job)
+}
+
+/// Replace names of matrix jobs with names of all their expanded jobs
+fn expand_names(names: &[String], variants: &HashMap<String, Vec<String>>) -> Vec<String> {
+    names
+        .iter()
+        .flat_map(|n| match variants.get(n) {
+            Some(v) => v.clone(),
+            None => vec![n.to_owned()],
+        })
+        .collect()
+}
+
+/// Expand jobs with matrix into job per combination named `<job> (<value>, ...)`
+///
+/// Needs and order referencing matrix job refer to all its expanded jobs.
+pub fn expand_matrix(
+    jobs: LinkedHashMap<String, Job>,
+    order: &mut Option<Vec<String>>,
+) -> Result<LinkedHashMap<String, Job>, String> {
+    let mut expanded = LinkedHashMap::new();
+    let mut variants = HashMap::new();
+    for (name, job) in jobs {
+        let matrix = match &job.matrix {
+            Some(m) => m,
+            None => {
+                expanded.insert(name, job);
+                continue;
+            }
+        };
+        let combinations = combinations(matrix);
+        if matrix.is_empty() || combinations.is_empty() {
+            return Err(format!("Matrix of job '{name}' has no combinations"));
+        }
+        let mut names = Vec::new();
+        for combination in combinations.iter() {
+            let values: Vec<&str> = combination.values().map(|v| v.as_str()).collect();
+            let variant = format!("{name} ({})", values.join(", "));
+            let variant_job =
+                expand_job(&job, combination).map_err(|e| format!("Job '{variant}': {e}"))?;
+            if expanded.insert(variant.clone(), variant_job).is_some() {
+                return Err(format!("Job '{variant}' is defined more than once"));
+            }
+            names.push(variant);
+        }
+        variants.insert(name, names);
+    }
+    if variants.is_empty() {
+        return Ok(expanded);
+    }
+
+    for (name, job) in expanded.iter_mut() {
+        for (c_name, container) in job.containers() {
+            if let Some(from) = container
+                .from_job
+                .as_ref()
+                .filter(|f| variants.contains_key(*f))
+            {
+                return Err(format!(
+                    "Container '{c_name}' of job '{name}' uses from_job '{from}' which has matrix"
+                ));
+            }
+        }
+        job.needs = match job.needs.take() {
+            Some(Needs::All(needs)) => Some(Needs::All(expand_names(&needs, &variants))),
+            Some(Needs::AnyOf { any_of }) => Some(Needs::AnyOf {
+                any_of: expand_names(&any_of, &variants),
+            }),
+            None => None,
+        };
+    }
+    if let Some(order) = order {
+        *order = expand_names(order, &variants);
+    }
+    Ok(expanded)
+}
@@ -0,0 +1,130 @@
+/// Validation of image references in `[registry[:port]/]name[:tag][@digest]` format
+///
+/// Images given with transport other than `docker://`, e.g. `oci-archive:/image.tar`,
+/// are passed to the container runtime as they are.
+const TRANSPORTS: [&str; 5] = [
+    "containers-storage:",
+    "dir:",
+    "docker-archive:",
+    "oci:",
+    "oci-archive:",
+];
+
+/// Lowercase alphanumeric parts joined by a single `.` or `_`, `__` or any number of `-`
+fn valid_component(component: &str) -> bool {
+    let bytes = component.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        while i < bytes.len() && (bytes[i].is_ascii_lowercase() || bytes[i].is_ascii_digit()) {
+            i += 1;
+        }
+        if i == start {
+            return false;
+        }
+        if i == bytes.len() {
+            return true;
+        }
+        let separator = i;
+        while i < bytes.len() && !(bytes[i].is_ascii_lowercase() || bytes[i].is_ascii_digit()) {
+            i += 1;
+        }
+        let valid_separator = match &component[separator..i] {
+            "." | "_" | "__" => true,
+            s => s.bytes().all(|b| b == b'-'),
+        };
+        if !valid_separator || i == bytes.len() {
+            return false;
+        }
+    }
+    false
+}
+
+/// Host name with optional port
+fn valid_registry(registry: &str) -> bool {
+    let (host, port) = match registry.rsplit_once(':') {
+        Some((h, p)) => (h, Some(p)),
+        None => (registry, None),
+    };
+    let valid_host = host.split('.').all(|label| {
+        !label.is_empty()
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    });
+    let valid_port = port.is_none_or(|p| !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit()));
+    valid_host && valid_port
+}
+
+fn valid_tag(tag: &str) -> bool {
+    let mut chars = tag.chars();
+    let first = chars.next();
+    tag.len() <= 128
+        && first.is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-')
+}
+
+/// `algorithm:hex`, e.g. `sha256:` followed by at least 32 hexadecimal digits
+fn valid_digest(digest: &str) -> bool {
+    match digest.split_once(':') {
+        Some((algorithm, hex)) => {
+            !algorithm.is_empty()
+                && algorithm.split(['+', '.', '_', '-']).all(|a| {
+                    !a.is_empty()
+                        && a.bytes()
+                            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+                })
+                && hex.len() >= 32
+                && hex.bytes().all(|b| b.is_ascii_hexdigit())
+        }
+        None => false,
+    }
+}
+
+/// Check image reference, error explains what part of it is invalid
+pub fn check_reference(image: &str) -> Result<(), String> {
+    if TRANSPORTS.iter().any(|t| image.starts_with(t)) {
+        return Ok(());
+    }
+    let reference = image.strip_prefix("docker://").unwrap_or(image);
+    if reference.is_empty() {
+        return Err("image reference is empty".to_owned());
+    }
+
+    let (name, digest) = match reference.split_once('@') {
+        Some((n, d)) => (n, Some(d)),
+        None => (reference, None),
+    };
+    if let Some(digest) = digest.filter(|d| !valid_digest(d)) {
+        return Err(format!("invalid digest '{digest}'"));
+    }
+    // Colon after the last slash separates tag, colon before it is registry port
+    let (name, tag) = match name.rsplit_once(':') {
+        Some((n, t)) if !t.contains('/') => (n, Some(t)),
+        _ => (name, None),
+    };
+    if let Some(tag) = tag.filter(|t| !valid_tag(t)) {
+        return Err(format!("invalid tag '{tag}'"));
+    }
+
+    let mut components: Vec<&str> = name.split('/').collect();
+    // First component is registry when it looks like host name
+    if components.len() > 1 {
+        let first = components[0];
+        if first.contains('.') || first.contains(':') || first == "localhost" {
+            if !valid_registry(first) {
+                return Err(format!("invalid registry '{first}'"));
+            }
+            components.remove(0);
+        }
+    }
+    match components.iter().find(|c| !valid_component(c)) {
+        Some(&"") => Err("empty name component".to_owned()),
+        Some(c) => Err(format!(
+            "invalid name component '{c}', only lowercase letters, digits and separators are allowed"
+        )),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,75 @@
+/// Machine readable JSON report of workflow run
+use linked_hash_map::LinkedHashMap;
+use serde_json::{json, Value};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::secrets;
+use crate::workflow::{JobResult, JobStatus, WorkflowError};
+
+fn job_report(name: &str, result: &JobResult) -> Value {
+    let steps: Vec<Value> = result
+        .steps
+        .iter()
+        .map(|(step, status)| json!({"name": step, "status": status.to_string()}))
+        .collect();
+    json!({
+        "name": name,
+        "status": result.status.to_string(),
+        "exit_code": result.exit_code,
+        "duration": result.duration.map(|d| d.as_secs_f64()),
+        "error": result.error,
+        "steps": steps,
+    })
+}
+
+/// Results of all jobs in workflow order together with workflow error as JSON
+///
+/// Jobs which were not started at all are reported as not run. Secret values are masked.
+pub fn render_report(
+    jobs: &[String],
+    jobs_status: &HashMap<String, JobResult>,
+    error: Option<&WorkflowError>,
+) -> Result<String, String> {
+    let not_run = JobResult {
+        status: JobStatus::NoStatus,
+        exit_code: None,
+        duration: None,
+        error: None,
+        steps: Vec::new(),
+        outputs: LinkedHashMap::new(),
+    };
+    let jobs: Vec<Value> = jobs
+        .iter()
+        .map(|name| job_report(name, jobs_status.get(name).unwrap_or(&not_run)))
+        .collect();
+    let report = json!({
+        "success": error.is_none(),
+        "error": error.map(|e| e.message()),
+        "error_kind": error.map(|e| e.kind()),
+        "jobs": jobs,
+    });
+    match serde_json::to_string_pretty(&report) {
+        Ok(data) => Ok(secrets::mask(&data) + "\n"),
+        Err(e) => Err(format!("Unable to serialize run report: {e}")),
+    }
+}
+
+/// Write report of the run, see `render_report`
+pub fn write_report(
+    path: &Path,
+    jobs: &[String],
+    jobs_status: &HashMap<String, JobResult>,
+    error: Option<&WorkflowError>,
+) -> Result<(), String> {
+    let data = render_report(jobs, jobs_status, error)?;
+    match fs::write(path, data) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!(
+            "Unable to write run report {}: {e}",
+            path.display()
+        )),
+    }
+}
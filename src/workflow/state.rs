@@ -0,0 +1,116 @@
+/// State of workflow run persisted for resuming failed runs with `--resume`
+use linked_hash_map::LinkedHashMap;
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::json;
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use crate::workflow::{Job, JobResult, JobStatus};
+
+#[derive(Deserialize)]
+struct StateJob {
+    name: String,
+    status: String,
+    #[serde(default)]
+    outputs: LinkedHashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct StateFile {
+    jobs: Vec<StateJob>,
+}
+
+/// Write status and outputs of all jobs in workflow order
+///
+/// Outputs may contain secrets, so the file is readable only by its owner.
+pub fn write_state(
+    path: &Path,
+    jobs: &[String],
+    jobs_status: &HashMap<String, JobResult>,
+) -> Result<(), String> {
+    let jobs: Vec<_> = jobs
+        .iter()
+        .map(|name| match jobs_status.get(name) {
+            Some(r) => json!({
+                "name": name,
+                "status": r.status.to_string(),
+                "outputs": r.outputs,
+            }),
+            None => json!({"name": name, "status": JobStatus::NoStatus.to_string()}),
+        })
+        .collect();
+    let state = json!({ "jobs": jobs });
+    let written = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut f| f.write_all(format!("{state:#}\n").as_bytes()));
+    written.map_err(|e| format!("Unable to write state file {}: {e}", path.display()))
+}
+
+/// Results of jobs which succeeded in the run recorded in state file, they are not run again
+///
+/// Detached jobs and jobs whose container is base of other jobs are run again, as their
+/// containers do not outlive the run. Jobs no longer in the workflow are ignored.
+pub fn load_state(
+    path: &Path,
+    jobs: &LinkedHashMap<String, Job>,
+) -> Result<HashMap<String, JobResult>, String> {
+    let data = fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read state file {}: {e}", path.display()))?;
+    let state: StateFile = serde_json::from_str(&data)
+        .map_err(|e| format!("Invalid state file {}: {e}", path.display()))?;
+
+    let recorded: HashSet<&str> = state.jobs.iter().map(|j| j.name.as_str()).collect();
+    let current: HashSet<&str> = jobs.keys().map(|j| j.as_str()).collect();
+    if recorded != current {
+        warn!(
+            "Jobs of the workflow changed since state file {} was written, only jobs present in both are resumed",
+            path.display()
+        );
+    }
+
+    let base_jobs: HashSet<&str> = jobs
+        .values()
+        .flat_map(|j| j.containers())
+        .filter_map(|(_, c)| c.from_job.as_deref())
+        .collect();
+    let mut resumed = HashMap::new();
+    for recorded in state.jobs {
+        let job = match jobs.get(&recorded.name) {
+            Some(j) => j,
+            None => continue,
+        };
+        if recorded.status != JobStatus::Success.to_string() {
+            continue;
+        }
+        if job.container.detach || base_jobs.contains(recorded.name.as_str()) {
+            info!(
+                "Job {} succeeded in previous run, but its container is needed again",
+                recorded.name
+            );
+            continue;
+        }
+        info!(
+            "Job {} succeeded in previous run, not running it again",
+            recorded.name
+        );
+        let result = JobResult {
+            status: JobStatus::Success,
+            exit_code: None,
+            duration: None,
+            error: None,
+            steps: Vec::new(),
+            outputs: recorded.outputs,
+        };
+        resumed.insert(recorded.name, result);
+    }
+    Ok(resumed)
+}
@@ -0,0 +1,109 @@
+/// Human readable summary of workflow run written to the log
+use log::info;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::secrets;
+use crate::workflow::{JobResult, JobStatus, StepStatus, WorkflowError};
+
+/// Status padded to column width, colored when enabled
+fn status_cell(status: JobStatus, width: usize, color: bool) -> String {
+    let cell = format!("{:width$}", status.to_string());
+    let code = match status {
+        JobStatus::Success => "32",
+        JobStatus::Failed => "31",
+        JobStatus::Skipped => "33",
+        JobStatus::NoStatus => return cell,
+    };
+    if color {
+        format!("\x1b[{code}m{cell}\x1b[0m")
+    } else {
+        cell
+    }
+}
+
+/// Log table of jobs in workflow order with their status and duration, and total duration
+///
+/// Jobs which were skipped or not run at all are shown with zero duration. Failed steps
+/// are listed under their job.
+pub fn log_summary(
+    jobs: &[String],
+    jobs_status: &HashMap<String, JobResult>,
+    total: Duration,
+    color: bool,
+) {
+    let name_width = jobs
+        .iter()
+        .map(|j| j.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(3);
+    let status_width = "not run".len();
+    info!("Job summary:");
+    info!(
+        "  {:name_width$}  {:status_width$}  {:>8}",
+        "JOB", "STATUS", "SECONDS"
+    );
+    for name in jobs {
+        let (status, duration) = match jobs_status.get(name) {
+            Some(r) => (r.status, r.duration.unwrap_or_default()),
+            None => (JobStatus::NoStatus, Duration::ZERO),
+        };
+        info!(
+            "  {name:name_width$}  {}  {:>8.1}",
+            status_cell(status, status_width, color),
+            duration.as_secs_f64()
+        );
+        let steps = jobs_status.get(name).map(|r| r.steps.as_slice());
+        for (step, _) in steps
+            .unwrap_or_default()
+            .iter()
+            .filter(|(_, s)| *s == StepStatus::Failed)
+        {
+            info!("    step {step} failed");
+        }
+    }
+    info!(
+        "  {:name_width$}  {:status_width$}  {:>8.1}",
+        "total",
+        "",
+        total.as_secs_f64()
+    );
+}
+
+/// Line per job in workflow order with its status, exit code and duration, followed by
+/// line with overall result and total duration
+///
+/// Values never contain spaces, exit code is left out when job has none.
+pub fn render_kv(
+    jobs: &[String],
+    jobs_status: &HashMap<String, JobResult>,
+    total: Duration,
+    error: Option<&WorkflowError>,
+) -> String {
+    let mut lines = Vec::new();
+    for name in jobs {
+        let (status, exit_code, duration) = match jobs_status.get(name) {
+            Some(r) => (r.status, r.exit_code, r.duration.unwrap_or_default()),
+            None => (JobStatus::NoStatus, None, Duration::ZERO),
+        };
+        let mut line = format!("job={name} status={}", status.to_string().replace(' ', "_"));
+        if let Some(code) = exit_code {
+            line.push_str(&format!(" exit_code={code}"));
+        }
+        line.push_str(&format!(" duration={:.1}", duration.as_secs_f64()));
+        lines.push(line);
+    }
+    let mut line = format!(
+        "success={} duration={:.1}",
+        error.is_none(),
+        total.as_secs_f64()
+    );
+    if let Some(e) = error {
+        line.push_str(&format!(" error_kind={}", e.kind()));
+    }
+    lines.push(line);
+    lines.push(String::new());
+    secrets::mask(&lines.join("\n"))
+}
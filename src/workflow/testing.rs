@@ -0,0 +1,293 @@
+/// Helpers of unit tests, engine recording operations instead of running containers
+use linked_hash_map::LinkedHashMap;
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::engines::{ContainerExec, ContainerOps, ContainerRun, ImageOps, VolumeOps};
+use crate::workflow::{
+    load_workflow, EnvOrder, Job, OutputFormat, PullPolicy, RunObserver, Runtime, TimeoutAction,
+    WorkflowError, WorkflowFormat, WorkflowOptions,
+};
+
+/// Options of a run without any command line option given, jobs run one by one
+pub fn options() -> WorkflowOptions {
+    WorkflowOptions {
+        dry_run: true,
+        cleanup: true,
+        privileged: true,
+        env: LinkedHashMap::new(),
+        env_override: LinkedHashMap::new(),
+        required_jobs: Vec::new(),
+        offline: false,
+        health_timeout: 60,
+        health_interval: 1,
+        tree: false,
+        list: false,
+        needs_closure: None,
+        job: None,
+        with_deps: false,
+        tags: Vec::new(),
+        fail_fast: None,
+        debug_shell: None,
+        validate_images: false,
+        validate: false,
+        format: WorkflowFormat::Yaml,
+        runtime: Runtime::Podman,
+        runtime_path: None,
+        podman_global_args: Vec::new(),
+        labels: Vec::new(),
+        timeout_action: TimeoutAction::Stop,
+        stop_timeout: None,
+        timeout: None,
+        pull_policy: PullPolicy::Missing,
+        max_image_age: None,
+        env_order: EnvOrder::Declared,
+        max_parallel: 1,
+        authfile: None,
+        log_dir: None,
+        result_dir: None,
+        newroot: None,
+        iguana_dir: None,
+        keep_volumes: false,
+        memory: None,
+        cpus: None,
+        report: None,
+        state_file: None,
+        resume: None,
+        color: false,
+        output_format: OutputFormat::Text,
+        workflow_file: None,
+        overlays: Vec::new(),
+        context: None,
+        workflow_dir: None,
+        pull_timeout: None,
+        pull_attempts: 1,
+        pull_retry_delay: Duration::ZERO,
+        run_id: String::new(),
+    }
+}
+
+/// Jobs of workflow given as YAML, loaded and ordered as for a real run
+pub fn jobs(workflow: &str) -> LinkedHashMap<String, Job> {
+    load_workflow(workflow, &options())
+        .unwrap_or_else(|e| panic!("Invalid test workflow: {e}"))
+        .jobs
+}
+
+/// Observer ignoring all events
+pub struct NoObserver;
+
+impl RunObserver for NoObserver {}
+
+/// Observer recording names of started jobs
+#[derive(Default)]
+pub struct StartObserver {
+    started: Mutex<Vec<String>>,
+}
+
+impl StartObserver {
+    pub fn started(&self) -> Vec<String> {
+        self.started.lock().expect("Started lock poisoned").clone()
+    }
+}
+
+impl RunObserver for StartObserver {
+    fn on_job_start(&self, job: &str) {
+        self.started
+            .lock()
+            .expect("Started lock poisoned")
+            .push(job.to_owned());
+    }
+}
+
+/// Tests aborting the workflow run one at a time, abort affects all runs in the process
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// Hold until the end of test which aborts or runs jobs
+pub fn serial() -> MutexGuard<'static, ()> {
+    SERIAL.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Engine recording operations, containers and step commands fail when asked to
+#[derive(Default)]
+pub struct MockEngine {
+    /// Names of containers which exit with error
+    failing: HashSet<String>,
+    /// Step commands which exit with error
+    failing_steps: HashSet<String>,
+    calls: Mutex<Vec<String>>,
+}
+
+impl MockEngine {
+    /// Engine on which given containers exit with error
+    pub fn failing(containers: &[&str]) -> Self {
+        MockEngine {
+            failing: containers.iter().map(|c| c.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Engine on which given step commands exit with error
+    pub fn failing_steps(commands: &[&str]) -> Self {
+        MockEngine {
+            failing_steps: commands.iter().map(|c| c.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Operations in order they were done, e.g. `run iguana-job-main` or `clean IMAGE`
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().expect("Calls lock poisoned").clone()
+    }
+
+    /// Names of containers run, in order they were started
+    pub fn runs(&self) -> Vec<String> {
+        self.calls()
+            .iter()
+            .filter_map(|c| c.strip_prefix("run "))
+            .map(str::to_owned)
+            .collect()
+    }
+
+    fn record(&self, call: String) {
+        self.calls.lock().expect("Calls lock poisoned").push(call);
+    }
+}
+
+impl ImageOps for MockEngine {
+    fn prepare_image(
+        &self,
+        image: &str,
+        _policy: PullPolicy,
+        _opts: &WorkflowOptions,
+    ) -> Result<(), WorkflowError> {
+        self.record(format!("pull {image}"));
+        Ok(())
+    }
+
+    fn image_exists(&self, _image: &str, _opts: &WorkflowOptions) -> Result<bool, String> {
+        Ok(true)
+    }
+
+    fn image_digest(&self, _image: &str, _opts: &WorkflowOptions) -> Result<String, String> {
+        Ok("sha256:0".to_owned())
+    }
+
+    fn clean_image(&self, image: &str, _opts: &WorkflowOptions) -> Result<(), String> {
+        self.record(format!("clean {image}"));
+        Ok(())
+    }
+}
+
+impl VolumeOps for MockEngine {
+    fn prepare_volume(&self, _volume_src: &str, _opts: &WorkflowOptions) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn clean_volumes(
+        &self,
+        _volumes: &HashSet<&str>,
+        _opts: &WorkflowOptions,
+    ) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl ContainerOps for MockEngine {
+    fn run_container(
+        &self,
+        run: ContainerRun,
+        _opts: &WorkflowOptions,
+    ) -> Result<Option<i32>, WorkflowError> {
+        self.record(format!("run {}", run.name));
+        match self.failing.contains(run.name) {
+            true => Err(WorkflowError::ContainerRun("exited with 1".to_owned())),
+            false => Ok(Some(0)),
+        }
+    }
+
+    fn stop_container(&self, name: &str, _opts: &WorkflowOptions) -> Result<(), String> {
+        self.record(format!("stop {name}"));
+        Ok(())
+    }
+
+    fn kill_container(&self, name: &str, _opts: &WorkflowOptions) -> Result<(), String> {
+        self.record(format!("kill {name}"));
+        Ok(())
+    }
+
+    fn remove_container(&self, _name: &str, _opts: &WorkflowOptions) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn commit_container(
+        &self,
+        _name: &str,
+        _image: &str,
+        _opts: &WorkflowOptions,
+    ) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn check_health(
+        &self,
+        _name: &str,
+        _cmd: &str,
+        _opts: &WorkflowOptions,
+    ) -> Result<bool, String> {
+        Ok(true)
+    }
+
+    fn dir_exists(
+        &self,
+        _name: &str,
+        _path: &str,
+        _opts: &WorkflowOptions,
+    ) -> Result<bool, String> {
+        Ok(true)
+    }
+
+    fn shell_exists(
+        &self,
+        _name: &str,
+        _shell: &str,
+        _opts: &WorkflowOptions,
+    ) -> Result<bool, String> {
+        Ok(true)
+    }
+
+    fn exec_in_container(
+        &self,
+        name: &str,
+        exec: ContainerExec,
+        _opts: &WorkflowOptions,
+    ) -> Result<Option<i32>, WorkflowError> {
+        self.record(format!("exec {name} {}", exec.cmd));
+        match self.failing_steps.contains(exec.cmd) {
+            true => Err(WorkflowError::ContainerRun("exited with 1".to_owned())),
+            false => Ok(Some(0)),
+        }
+    }
+
+    fn copy_from_container(
+        &self,
+        _name: &str,
+        _src: &str,
+        _dest: &Path,
+        _opts: &WorkflowOptions,
+    ) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn follow_logs(
+        &self,
+        _name: &str,
+        _path: &Path,
+        _opts: &WorkflowOptions,
+    ) -> Result<(), String> {
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+/// Human readable tree view of the workflow plan
+use linked_hash_map::LinkedHashMap;
+
+use crate::workflow::{Container, Needs, Workflow, FINALLY_JOB};
+
+const INDENT: &str = "  ";
+
+/// Sorted keys of environment map, values are not shown as they may contain secrets
+fn env_keys(env: &LinkedHashMap<String, String>) -> String {
+    let mut keys: Vec<&str> = env.keys().map(|k| k.as_str()).collect();
+    keys.sort_unstable();
+    keys.join(", ")
+}
+
+fn render_container(lines: &mut Vec<String>, depth: usize, container: &Container) {
+    let indent = INDENT.repeat(depth);
+    match &container.from_job {
+        Some(from) => lines.push(format!("{indent}from_job: {from}")),
+        None => lines.push(format!("{indent}image: {}", container.image)),
+    }
+    if let Some(env) = &container.env {
+        lines.push(format!("{indent}env: {}", env_keys(env)));
+    }
+    if let Some(volumes) = &container.volumes {
+        lines.push(format!("{indent}volumes: {}", volumes.join(", ")));
+    }
+    if let Some(tmpfs) = &container.tmpfs {
+        lines.push(format!("{indent}tmpfs: {}", tmpfs.join(", ")));
+    }
+    if let Some(devices) = &container.devices {
+        lines.push(format!("{indent}devices: {}", devices.join(", ")));
+    }
+    if let Some(gpus) = &container.gpus {
+        lines.push(format!("{indent}gpus: {gpus}"));
+    }
+    if let Some(network) = &container.network {
+        lines.push(format!("{indent}network: {network}"));
+    }
+    if let Some(hostname) = &container.hostname {
+        lines.push(format!("{indent}hostname: {hostname}"));
+    }
+    for (host, ip) in container.extra_hosts.iter().flatten() {
+        lines.push(format!("{indent}extra host: {host} {ip}"));
+    }
+    if let Some(ports) = &container.ports {
+        lines.push(format!("{indent}ports: {}", ports.join(", ")));
+    }
+    if let Some(memory) = &container.memory {
+        lines.push(format!("{indent}memory: {memory}"));
+    }
+    if let Some(cpus) = &container.cpus {
+        lines.push(format!("{indent}cpus: {cpus}"));
+    }
+    if let Some(entrypoint) = &container.entrypoint {
+        lines.push(format!("{indent}entrypoint: {entrypoint:?}"));
+    }
+    if let Some(dir) = &container.working_directory {
+        lines.push(format!("{indent}working_directory: {dir}"));
+    }
+    if let Some(restart) = &container.restart {
+        lines.push(format!("{indent}restart: {}", restart.as_str()));
+    }
+    if let Some(user) = &container.user {
+        lines.push(format!("{indent}user: {user}"));
+    }
+    if let Some(labels) = &container.labels {
+        lines.push(format!("{indent}labels: {}", env_keys(labels)));
+    }
+    if let Some(command) = &container.command {
+        lines.push(format!("{indent}command: {command:?}"));
+    }
+    if container.detach {
+        lines.push(format!("{indent}detach: true"));
+    }
+    if container.optional {
+        lines.push(format!("{indent}optional: true"));
+    }
+    if let Some(check) = &container.healthcheck {
+        lines.push(format!("{indent}healthcheck: {}", check.cmd));
+    }
+}
+
+/// Needed jobs joined for one-line display, `-` when job needs nothing
+fn needs_list(needs: Option<&Needs>) -> String {
+    match needs {
+        Some(Needs::All(needs)) if !needs.is_empty() => needs.join(", "),
+        Some(Needs::AnyOf { any_of }) => format!("any of {}", any_of.join(", ")),
+        _ => "-".to_owned(),
+    }
+}
+
+/// Render table of jobs in the order they run with their image, needs and services
+pub fn render_list(workflow: &Workflow) -> String {
+    let mut rows = vec![[
+        "JOB".to_owned(),
+        "IMAGE".to_owned(),
+        "NEEDS".to_owned(),
+        "SERVICES".to_owned(),
+    ]];
+    for (name, job) in workflow.jobs.iter() {
+        let image = match &job.container.from_job {
+            Some(from) => format!("from job {from}"),
+            None => job.container.image.to_owned(),
+        };
+        let mut services: Vec<&str> = job
+            .services
+            .iter()
+            .flatten()
+            .map(|(s, _)| s.as_str())
+            .collect();
+        services.sort_unstable();
+        let services = if services.is_empty() {
+            "-".to_owned()
+        } else {
+            services.join(", ")
+        };
+        rows.push([
+            name.to_owned(),
+            image,
+            needs_list(job.needs.as_ref()),
+            services,
+        ]);
+    }
+
+    let mut widths = [0; 3];
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut lines: Vec<String> = rows
+        .iter()
+        .map(|row| {
+            format!(
+                "{:w0$}{INDENT}{:w1$}{INDENT}{:w2$}{INDENT}{}",
+                row[0],
+                row[1],
+                row[2],
+                row[3],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2]
+            )
+        })
+        .collect();
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// Render indented tree of jobs, their services and steps
+pub fn render_tree(workflow: &Workflow) -> String {
+    let mut lines = vec![workflow
+        .name
+        .as_deref()
+        .unwrap_or("control file")
+        .to_owned()];
+    if let Some(env) = &workflow.env {
+        if !env.is_empty() {
+            lines.push(format!("{INDENT}env: {}", env_keys(env)));
+        }
+    }
+
+    let job_indent = INDENT;
+    let item_indent = INDENT.repeat(2);
+    let step_indent = INDENT.repeat(3);
+    // Finally job runs last regardless of results of other jobs
+    let jobs = workflow.jobs.iter().map(|(name, job)| (name.as_str(), job));
+    let finally = workflow.finally.iter().map(|job| (FINALLY_JOB, job));
+    for (name, job) in jobs.chain(finally) {
+        lines.push(format!("{job_indent}job {name}"));
+        match &job.needs {
+            Some(Needs::All(needs)) => {
+                lines.push(format!("{item_indent}needs: {}", needs.join(", ")))
+            }
+            Some(Needs::AnyOf { any_of }) => {
+                lines.push(format!("{item_indent}needs any of: {}", any_of.join(", ")))
+            }
+            None => {}
+        }
+        if let Some(tags) = &job.tags {
+            lines.push(format!("{item_indent}tags: {}", tags.join(", ")));
+        }
+        if let Some(condition) = &job.condition {
+            lines.push(format!("{item_indent}if: {condition}"));
+        }
+        if let Some(secrets) = &job.secrets {
+            lines.push(format!("{item_indent}secrets: {}", env_keys(secrets)));
+        }
+        if job.continue_on_error {
+            lines.push(format!("{item_indent}continue_on_error: true"));
+        }
+        lines.push(format!("{item_indent}container main"));
+        render_container(&mut lines, 3, &job.container);
+
+        if let Some(services) = &job.services {
+            for (s_name, s_container) in services.iter() {
+                lines.push(format!("{item_indent}service {s_name}"));
+                render_container(&mut lines, 3, s_container);
+            }
+        }
+
+        let pre = job.pre.iter().flatten().map(|s| ("pre step", s));
+        let steps = job.steps.iter().flatten().map(|s| ("step", s));
+        let post = job.post.iter().flatten().map(|s| ("post step", s));
+        for (i, (kind, step)) in pre.chain(steps).chain(post).enumerate() {
+            match &step.name {
+                Some(s_name) => lines.push(format!("{item_indent}{kind} {} {s_name}", i + 1)),
+                None => lines.push(format!("{item_indent}{kind} {}", i + 1)),
+            }
+            if let Some(id) = &step.id {
+                lines.push(format!("{step_indent}id: {id}"));
+            }
+            if let Some(condition) = &step.condition {
+                lines.push(format!("{step_indent}if: {condition}"));
+            }
+            if let Some(uses) = &step.uses {
+                lines.push(format!("{step_indent}uses: {uses}"));
+            }
+            if let Some(run) = &step.run {
+                lines.push(format!("{step_indent}run: {run}"));
+            }
+            if let Some(shell) = &step.shell {
+                lines.push(format!("{step_indent}shell: {shell}"));
+            }
+            if let Some(with) = &step.with {
+                lines.push(format!("{step_indent}with: {}", env_keys(with)));
+            }
+            if let Some(env) = &step.env {
+                lines.push(format!("{step_indent}env: {}", env_keys(env)));
+            }
+            if let Some(dir) = &step.working_directory {
+                lines.push(format!("{step_indent}working_directory: {dir}"));
+            }
+            if step.continue_on_error {
+                lines.push(format!("{step_indent}continue_on_error: true"));
+            }
+        }
+    }
+
+    lines.push(String::new());
+    lines.join("\n")
+}